@@ -1,5 +1,18 @@
 use std::fmt;
 
+// Where a node starts in the source it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Expr {
     Number(f64),
@@ -18,6 +31,7 @@ pub enum Expr {
     Call {
         callee: Box<Expr>,
         arguments: Vec<Expr>,
+        span: Span,
     },
     // New: Special syntax for create commands
     CreateCall {
@@ -28,6 +42,35 @@ pub enum Expr {
         name: String,
         value: Box<Expr>,
     },
+    // and/or, kept apart from Binary because the right side may not be evaluated
+    Logical {
+        left: Box<Expr>,
+        operator: LogicalOp,
+        right: Box<Expr>,
+    },
+    Range {       // start..end, inclusive on both ends; evaluates to a list
+        start: Box<Expr>,
+        end: Box<Expr>,
+    },
+    List(Vec<Expr>), // [a, b, c]
+    Index {       // list[index]
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    IndexAssign { // list[index] = value
+        object: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Get {         // ball1.speed
+        object: Box<Expr>,
+        property: String,
+    },
+    Set {         // ball1.volume = 0.5
+        object: Box<Expr>,
+        property: String,
+        value: Box<Expr>,
+    },
     // Remove HitsThreshold variant
 }
 
@@ -37,6 +80,7 @@ pub enum BinaryOp {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
     Equal,
     NotEqual,
     Less,
@@ -46,6 +90,12 @@ pub enum BinaryOp {
     Hits,  // New: for "ball1 hits self X" syntax
 }
 
+#[derive(Debug, Clone)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
 #[derive(Debug, Clone)]
 pub enum UnaryOp {
     Minus,
@@ -60,6 +110,10 @@ pub enum SpeedModification {
 
 #[derive(Debug, Clone)]
 pub enum Stmt {
+    Spanned {     // Any statement, tagged with where it starts
+        span: Span,
+        stmt: Box<Stmt>,
+    },
     Expression(Expr),
     Let {
         name: String,
@@ -74,6 +128,11 @@ pub enum Stmt {
         condition: Expr,
         body: Box<Stmt>,
     },
+    For {
+        variable: String,
+        iterable: Expr, // A Range or anything evaluating to a list
+        body: Box<Stmt>,
+    },
     Block(Vec<Stmt>),
     Function {
         name: String,
@@ -93,6 +152,11 @@ pub enum Stmt {
         object_name: String,
         speed: SpeedModification,
     },
+    SetProperty { // set pitch ball1 +7, set rate ball1 0.5, set pan ball1 -0.5
+        object_name: String,
+        property: String,
+        value: Expr,
+    },
     Label {
         object_name: String,
         arguments: Vec<Expr>,
@@ -116,11 +180,42 @@ pub enum Stmt {
         script_name: String,
     },
     Slice {       // New: slice array command
-        sequence: Vec<f64>, // The sequence of marker numbers
+        sequence: Vec<Expr>, // Marker numbers, or lists of them
+        pitches: Vec<Expr>,  // Semitones per step, cycled; empty leaves the pitch alone
+    },
+    SliceInto {   // slice into 16 [ball1 | "loop.wav"]: equal slice markers on a sample
+        divisions: Expr,
+        target: Option<Expr>, // Ball or sample file; the script's ball when left out
     },
     Waveform {    // New: waveform editor command
         target: Option<String>, // Optional audio file path or ball reference
     },
+    Fx {          // fx ball1 delay 0.25 0.4, fx master reverb 0.8, fx ball1 delay off, fx ball1 off
+        target: String,       // Ball name, variable holding a ball, self, cursor or master
+        effect: String,       // lowpass, highpass, delay, reverb, or off for the whole chain
+        arguments: Vec<Expr>,
+        remove: bool,         // fx ball1 delay off
+    },
+    MidiRecord {  // midi_record start [volume|speed], midi_record stop "take1.mid"
+        start: bool,
+        velocity: Option<String>, // What sets note velocity: volume (default) or speed
+        path: Option<Expr>,       // File written on stop
+    },
+    On {          // Event handler: on wall { ... }, on hit ball2 { ... }
+        event: String,          // wall, tick, play, stop or hit
+        target: Option<String>, // Only for hit: the object that must be involved
+        body: Box<Stmt>,
+    },
+}
+
+impl Stmt {
+    // The statement without its span wrapper
+    pub fn inner(&self) -> &Stmt {
+        match self {
+            Stmt::Spanned { stmt, .. } => stmt.inner(),
+            other => other,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -179,7 +274,7 @@ impl fmt::Display for Expr {
             Expr::Unary { operator, operand } => {
                 write!(f, "({:?} {})", operator, operand)
             },
-            Expr::Call { callee, arguments } => {
+            Expr::Call { callee, arguments, .. } => {
                 write!(f, "{}(", callee)?;
                 for (i, arg) in arguments.iter().enumerate() {
                     if i > 0 { write!(f, ", ")?; }
@@ -198,6 +293,32 @@ impl fmt::Display for Expr {
             Expr::Assignment { name, value } => {
                 write!(f, "{} = {}", name, value)
             },
+            Expr::Logical { left, operator, right } => {
+                write!(f, "({} {:?} {})", left, operator, right)
+            },
+            Expr::Range { start, end } => {
+                write!(f, "{}..{}", start, end)
+            },
+            Expr::List(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 { write!(f, ", ")?; }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            },
+            Expr::Index { object, index } => {
+                write!(f, "{}[{}]", object, index)
+            },
+            Expr::IndexAssign { object, index, value } => {
+                write!(f, "{}[{}] = {}", object, index, value)
+            },
+            Expr::Get { object, property } => {
+                write!(f, "{}.{}", object, property)
+            },
+            Expr::Set { object, property, value } => {
+                write!(f, "{}.{} = {}", object, property, value)
+            },
             // Remove this entire HitsThreshold match arm (lines 195-197)
         }
     }
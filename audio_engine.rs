@@ -1,10 +1,14 @@
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::{Decoder, OutputStream, Source};
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::Cursor;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
+use crate::mixer::{Mixer, MixerState, Voice, MIXER_CHANNELS, MIXER_SAMPLE_RATE};
+use crate::synth::SynthSettings;
+use crate::effects::EffectSettings;
+use crate::project::{self, MarkerFile};
 
 #[derive(Error, Debug)]
 pub enum AudioError {
@@ -18,28 +22,163 @@ pub enum AudioError {
 
 #[derive(Clone)]
 pub struct AudioSample {
-    pub data: Arc<Vec<u8>>,
+    pub pcm: Arc<Vec<f32>>, // Interleaved PCM, decoded once at load time
+    pub sample_rate: u32,
+    pub channels: u16,
     pub file_path: String,
     pub slice_markers: Vec<f64>, // Time positions in seconds for slice markers
+    pub content_hash: u64, // FNV-1a of the file, to tell whether saved markers still fit
+}
+
+impl AudioSample {
+    pub fn frames(&self) -> usize {
+        self.pcm.len() / self.channels.max(1) as usize
+    }
+    
+    pub fn duration(&self) -> f64 {
+        if self.sample_rate == 0 {
+            0.0
+        } else {
+            self.frames() as f64 / self.sample_rate as f64
+        }
+    }
+    
+    // Frame range covering start_time..end_time seconds (end may be INFINITY)
+    pub fn frame_range(&self, start_time: f64, end_time: f64) -> (usize, usize) {
+        let rate = self.sample_rate as f64;
+        let total_frames = self.frames();
+        let start = ((start_time.max(0.0) * rate).round() as usize).min(total_frames);
+        let end = if end_time.is_finite() {
+            ((end_time * rate).round() as usize).clamp(start, total_frames)
+        } else {
+            total_frames
+        };
+        (start, end)
+    }
+    
+    /// Writes the slices between markers to `<directory>/<name>_01.wav` onwards,
+    /// numbered without gaps. The files of the previous export of this sample,
+    /// as listed in its `<file>.slices.json` manifest, are removed first so none
+    /// are left over from a longer one; other files in the directory are kept.
+    pub fn export_slices(&self, directory: &Path) -> Result<Vec<String>, AudioError> {
+        let sample_path = Path::new(&self.file_path);
+        let name = sample_path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "slice".to_string());
+        let file_name = sample_path.file_name()
+            .map(|file| file.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.clone());
+        std::fs::create_dir_all(directory)
+            .map_err(|e| AudioError::PlaybackError(format!("Cannot create {}: {}", directory.display(), e)))?;
+        let manifest = slices_manifest_path(directory, &file_name);
+        remove_exported_slices(directory, &manifest)?;
+        
+        let channels = self.channels.max(1) as usize;
+        let mut written = Vec::new();
+        let mut written_names = Vec::new();
+        for (index, &start_time) in self.slice_markers.iter().enumerate() {
+            let end_time = self.slice_markers.get(index + 1).copied().unwrap_or(f64::INFINITY);
+            let (start, end) = self.frame_range(start_time, end_time);
+            if end <= start {
+                continue; // Two markers on the same spot
+            }
+            let slice_name = format!("{}_{:02}.wav", name, written.len() + 1);
+            let path = directory.join(&slice_name);
+            write_wav_file(&path, &self.pcm[start * channels..end * channels], self.sample_rate, self.channels)?;
+            written.push(path.to_string_lossy().replace('\\', "/"));
+            written_names.push(slice_name);
+        }
+        
+        let json = serde_json::to_string_pretty(&written_names)
+            .map_err(|e| AudioError::PlaybackError(format!("Cannot serialize slice list: {}", e)))?;
+        std::fs::write(&manifest, json)
+            .map_err(|e| AudioError::PlaybackError(format!("Cannot write {}: {}", manifest.display(), e)))?;
+        Ok(written)
+    }
+}
+
+// samples/ + loop.wav -> samples/loop.wav.slices.json, the files the last export wrote
+fn slices_manifest_path(directory: &Path, file_name: &str) -> std::path::PathBuf {
+    directory.join(format!("{}.slices.json", file_name))
+}
+
+// Deletes the files listed in the manifest of an earlier export_slices, if there was one
+fn remove_exported_slices(directory: &Path, manifest: &Path) -> Result<(), AudioError> {
+    if !manifest.exists() {
+        return Ok(());
+    }
+    let json = std::fs::read_to_string(manifest)
+        .map_err(|e| AudioError::PlaybackError(format!("Cannot read {}: {}", manifest.display(), e)))?;
+    let names: Vec<String> = serde_json::from_str(&json)
+        .map_err(|e| AudioError::PlaybackError(format!("Invalid slice list {}: {}", manifest.display(), e)))?;
+    for name in names {
+        // Only bare file names inside the export directory, whatever the manifest says
+        let Some(file) = Path::new(&name).file_name() else { continue };
+        let path = directory.join(file);
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(AudioError::PlaybackError(format!("Cannot replace {}: {}", path.display(), e))),
+        }
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
 pub struct SliceArray {
     pub sample_key: String,
     pub sequence: Vec<usize>, // Sequence of marker indices to play
+    pub pitches: Vec<f64>, // Semitones for each step of the sequence (cycled), empty = unchanged
     pub current_index: usize,
 }
 
+// Output format used for offline renders (same as the live mixer)
+pub const RENDER_SAMPLE_RATE: u32 = MIXER_SAMPLE_RATE;
+pub const RENDER_CHANNELS: u16 = MIXER_CHANNELS;
+// Longest offline render, which bounds the size of the mix buffer
+pub const MAX_RENDER_SECONDS: f64 = 3600.0;
+
+// How far ahead of the mixer's render position new sounds are scheduled. This
+// absorbs the gap between physics frames so collision spacing stays exact.
+const SCHEDULE_LATENCY: f64 = 0.05;
+// Collisions further ahead than this are treated as a timeline jump (pause, hitch)
+const MAX_SCHEDULE_AHEAD: f64 = 0.5;
+// Rendered synth notes kept for reuse before the cache is emptied
+const MAX_SYNTH_NOTES: usize = 512;
+
+// A sound triggered while an offline render is being captured
+#[derive(Clone, Debug)]
+pub struct CapturedVoice {
+    pub sample_key: String,
+    pub time: f64,       // Simulation time in seconds when the sound starts
+    pub start_time: f64, // Offset into the sample in seconds
+    pub end_time: f64,   // End offset in seconds, or INFINITY for the whole sample
+    pub volume: f32,
+    pub rate: f64,       // Playback rate, 1.0 = original pitch
+    pub pan: f32,        // -1.0 = left, 1.0 = right
+    pub bus: Option<u32>, // Ball whose effects the sound goes through
+    pub effects: Vec<EffectSettings>, // That ball's effects when the sound started
+}
+
 // Remove the global static and make AudioEngine thread-local instead
 thread_local! {
     static AUDIO_ENGINE: std::cell::RefCell<Option<AudioEngine>> = std::cell::RefCell::new(None);
 }
 
 pub struct AudioEngine {
-    _stream: OutputStream,
-    stream_handle: OutputStreamHandle,
+    // Both are None in headless mode, where triggered sounds are silently dropped
+    _stream: Option<OutputStream>,
+    mixer: Option<Mixer>,
     samples: HashMap<String, AudioSample>,
+    synth_notes: HashMap<String, AudioSample>, // Rendered synth notes by SynthSettings::note_key
     slice_arrays: HashMap<String, SliceArray>, // Store slice arrays by name
+    polyphony: usize,
+    trigger_time: Option<f64>, // Simulation time of the collision currently triggering sounds
+    trigger_delay: f64, // Seconds from trigger_time to the quantize grid line the sounds start on
+    trigger_bus: Option<(u32, Vec<EffectSettings>)>, // Effect bus of the ball whose collision triggers sounds
+    master_effects: Vec<EffectSettings>,
+    timeline_anchor: Option<(f64, u64)>, // (simulation time, mixer frame) pair used for scheduling
+    render_capture: std::cell::RefCell<Option<Vec<CapturedVoice>>>, // Some while an offline render is running
 }
 
 impl AudioEngine {
@@ -47,12 +186,48 @@ impl AudioEngine {
         let (_stream, stream_handle) = OutputStream::try_default()
             .map_err(|e| AudioError::InitError(format!("Failed to create audio stream: {}", e)))?;
         
-        Ok(Self {
-            _stream,
-            stream_handle,
+        // One mixer source runs for the lifetime of the stream; sounds are scheduled into it
+        let mixer = Mixer::new();
+        stream_handle.play_raw(mixer.source())
+            .map_err(|e| AudioError::InitError(format!("Failed to start mixer: {}", e)))?;
+        
+        let mut engine = Self::new_headless();
+        engine._stream = Some(_stream);
+        engine.mixer = Some(mixer);
+        Ok(engine)
+    }
+    
+    // Create an engine without an output device, for running scenes without a sound card
+    pub fn new_headless() -> Self {
+        Self {
+            _stream: None,
+            mixer: None,
             samples: HashMap::new(),
+            synth_notes: HashMap::new(),
             slice_arrays: HashMap::new(),
-        })
+            polyphony: MixerState::new().polyphony(),
+            trigger_time: None,
+            trigger_delay: 0.0,
+            trigger_bus: None,
+            master_effects: Vec::new(),
+            timeline_anchor: None,
+            render_capture: std::cell::RefCell::new(None),
+        }
+    }
+    
+    pub fn is_headless(&self) -> bool {
+        self.mixer.is_none()
+    }
+    
+    pub fn set_polyphony(&mut self, polyphony: usize) {
+        self.polyphony = polyphony.max(1);
+        if let Some(ref mixer) = self.mixer {
+            mixer.set_polyphony(self.polyphony);
+        }
+    }
+    
+    pub fn get_polyphony(&self) -> usize {
+        self.polyphony
     }
     
     pub fn load_audio_file<P: AsRef<Path>>(&mut self, file_path: P) -> Result<String, AudioError> {
@@ -75,23 +250,25 @@ impl AudioEngine {
         let actual_path = samples_path.as_ref().map(|s| Path::new(s)).unwrap_or(path);
         let actual_path_str = actual_path.to_string_lossy().to_string();
         
-        // Read the entire file into memory for fast playback
-        let file = File::open(actual_path)
+        // Decode the whole file once; playback, slicing and duration all read this buffer
+        let bytes = std::fs::read(actual_path)
             .map_err(|e| AudioError::LoadError(format!("Cannot open file {}: {}", actual_path_str, e)))?;
+        let content_hash = project::fnv1a_hash(&bytes);
         
-        // Validate that the file can be decoded
-        let buf_reader = BufReader::new(file);
-        let _decoder = Decoder::new(buf_reader)
+        let decoder = Decoder::new(Cursor::new(bytes))
             .map_err(|e| AudioError::LoadError(format!("Cannot decode audio file {}: {}", actual_path_str, e)))?;
         
-        // Read file data into memory
-        let file_data = std::fs::read(actual_path)
-            .map_err(|e| AudioError::LoadError(format!("Cannot read file {}: {}", actual_path_str, e)))?;
+        let sample_rate = decoder.sample_rate();
+        let channels = decoder.channels();
+        let pcm: Vec<f32> = decoder.convert_samples::<f32>().collect();
         
         let sample = AudioSample {
-            data: Arc::new(file_data),
+            pcm: Arc::new(pcm),
+            sample_rate,
+            channels,
             file_path: actual_path_str.clone(),
-            slice_markers: Vec::new(), // Initialize with empty markers
+            slice_markers: load_saved_markers(&actual_path_str, sample_rate, content_hash),
+            content_hash,
         };
         
         // Store the sample using the original path as key for consistency
@@ -100,41 +277,45 @@ impl AudioEngine {
         Ok(path_str)
     }
     
-    pub fn play_sample(&self, sample_key: &str) -> Result<(), AudioError> {
-        let sample = self.samples.get(sample_key)
-            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
-        
-        // Create a cursor from the in-memory data
-        let cursor = std::io::Cursor::new(sample.data.as_ref().clone());
-        let decoder = Decoder::new(cursor)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to decode sample: {}", e)))?;
-        
-        // Create a new sink for this playback
-        let sink = Sink::try_new(&self.stream_handle)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to create sink: {}", e)))?;
-        
-        sink.append(decoder);
-        sink.detach(); // Let it play independently
-        
-        Ok(())
+    pub fn play_sample(&mut self, sample_key: &str) -> Result<(), AudioError> {
+        self.play_sample_with_settings(sample_key, 1.0, 1.0, 0.0)
     }
     
-    pub fn play_sample_with_volume(&self, sample_key: &str, volume: f32) -> Result<(), AudioError> {
-        let sample = self.samples.get(sample_key)
-            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
-        
-        let cursor = std::io::Cursor::new(sample.data.as_ref().clone());
-        let decoder = Decoder::new(cursor)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to decode sample: {}", e)))?;
-        
-        let sink = Sink::try_new(&self.stream_handle)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to create sink: {}", e)))?;
-        
-        sink.set_volume(volume.clamp(0.0, 1.0));
-        sink.append(decoder);
-        sink.detach();
-        
-        Ok(())
+    pub fn play_sample_with_volume(&mut self, sample_key: &str, volume: f32) -> Result<(), AudioError> {
+        self.play_sample_with_settings(sample_key, volume, 1.0, 0.0)
+    }
+    
+    // Play resampled from the decoded PCM (rate 2.0 is an octave up at double speed),
+    // panned from -1.0 (left) to 1.0 (right)
+    pub fn play_sample_with_settings(&mut self, sample_key: &str, volume: f32, rate: f64, pan: f32) -> Result<(), AudioError> {
+        self.trigger_voice(sample_key, 0.0, f64::INFINITY, volume.clamp(0.0, 1.0), rate, pan)
+    }
+    
+    // Play one synth note, rendering it on first use. Pitch changes the oscillator
+    // frequency; rate still resamples the rendered note like a sample.
+    pub fn play_synth_note(&mut self, settings: &SynthSettings, frequency: f64, volume: f32, rate: f64, pan: f32) -> Result<(), AudioError> {
+        let key = settings.note_key(frequency);
+        if !self.synth_notes.contains_key(&key) {
+            // Notes captured for a running render must still be there when it is mixed
+            if self.synth_notes.len() >= MAX_SYNTH_NOTES && self.render_capture.borrow().is_none() {
+                self.synth_notes.clear();
+            }
+            let note = AudioSample {
+                pcm: Arc::new(settings.render(frequency, MIXER_SAMPLE_RATE)),
+                sample_rate: MIXER_SAMPLE_RATE,
+                channels: 1,
+                file_path: key.clone(),
+                slice_markers: Vec::new(),
+                content_hash: 0,
+            };
+            self.synth_notes.insert(key.clone(), note);
+        }
+        self.trigger_voice(&key, 0.0, f64::INFINITY, volume.clamp(0.0, 1.0), rate, pan)
+    }
+    
+    // A loaded sample, or a rendered synth note
+    fn find_sample(&self, sample_key: &str) -> Option<&AudioSample> {
+        self.samples.get(sample_key).or_else(|| self.synth_notes.get(sample_key))
     }
     
     pub fn get_loaded_samples(&self) -> Vec<String> {
@@ -152,16 +333,31 @@ impl AudioEngine {
             return Err(AudioError::PlaybackError(format!("Sample not found: {}", sample_key)));
         }
         
+        // Scripts re-run the slice command on every hit, so carry on from where the old sequence was
+        let current_index = match self.slice_arrays.get(&name) {
+            Some(existing) if !sequence.is_empty() => existing.current_index % sequence.len(),
+            _ => 0,
+        };
+        
         let slice_array = SliceArray {
             sample_key,
             sequence,
-            current_index: 0,
+            pitches: Vec::new(),
+            current_index,
         };
         
         self.slice_arrays.insert(name, slice_array);
         Ok(())
     }
     
+    pub fn set_slice_pitches(&mut self, name: &str, pitches: Vec<f64>) -> Result<(), AudioError> {
+        let slice_array = self.slice_arrays.get_mut(name)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Slice array not found: {}", name)))?;
+        
+        slice_array.pitches = pitches;
+        Ok(())
+    }
+    
     pub fn set_sample_markers(&mut self, sample_key: &str, markers: Vec<f64>) -> Result<(), AudioError> {
         let sample = self.samples.get_mut(sample_key)
             .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
@@ -170,6 +366,19 @@ impl AudioEngine {
         Ok(())
     }
     
+    /// Writes a sample's markers next to its file, so they come back the next time
+    /// it is loaded. Returns the path written.
+    pub fn save_sample_markers(&self, sample_key: &str) -> Result<String, AudioError> {
+        let sample = self.samples.get(sample_key)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
+        
+        let path = project::markers_path(&sample.file_path);
+        MarkerFile::new(sample.sample_rate, sample.content_hash, sample.slice_markers.clone())
+            .save(&path)
+            .map_err(AudioError::PlaybackError)?;
+        Ok(path.to_string_lossy().to_string())
+    }
+    
     pub fn get_sample_markers(&self, sample_key: &str) -> Result<Vec<f64>, AudioError> {
         let sample = self.samples.get(sample_key)
             .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
@@ -181,25 +390,45 @@ impl AudioEngine {
         let sample = self.samples.get(sample_key)
             .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
         
-        // Clone the data to avoid lifetime issues
-        let data_clone = sample.data.as_ref().clone();
-        let cursor = std::io::Cursor::new(data_clone);
-        let decoder = Decoder::new(cursor)
-            .map_err(|e| AudioError::PlaybackError(format!("Cannot decode sample: {}", e)))?;
-        
-        let sample_rate = decoder.sample_rate() as f64;
-        let channels = decoder.channels() as f64;
-        
-        // Get total samples by consuming the decoder
-        let total_samples = decoder.count() as f64;
-        let duration = total_samples / (sample_rate * channels);
+        Ok(sample.duration())
+    }
+    
+    // Length in frames and sample rate of a loaded sample
+    pub fn get_sample_frames(&self, sample_key: &str) -> Result<(usize, u32), AudioError> {
+        let sample = self.samples.get(sample_key)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
         
-        Ok(duration)
+        Ok((sample.frames(), sample.sample_rate))
     }
     
-    pub fn play_slice_array(&mut self, array_name: &str) -> Result<(), AudioError> {
+    // Key of a loaded sample: `name` itself, or the sample loaded from a file of that name
+    pub fn find_sample_key(&self, name: &str) -> Option<String> {
+        if self.samples.contains_key(name) {
+            return Some(name.to_string());
+        }
+        let file_name = Path::new(name).file_name()?;
+        let mut keys: Vec<&String> = self.samples.keys()
+            .filter(|key| Path::new(key.as_str()).file_name() == Some(file_name))
+            .collect();
+        keys.sort();
+        keys.first().map(|key| key.to_string())
+    }
+    
+    /// Writes each slice of a sample (marker to next marker, the last one to the end)
+    /// to `<directory>/<name>_01.wav`, `_02.wav`, ... and returns the paths written.
+    pub fn export_slices(&self, sample_key: &str, directory: &Path) -> Result<Vec<String>, AudioError> {
+        let sample = self.samples.get(sample_key)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
+        if sample.slice_markers.is_empty() {
+            return Err(AudioError::PlaybackError(format!("{} has no slice markers", sample_key)));
+        }
+        sample.export_slices(directory)
+    }
+    
+    // `rate` and `pan` come from the triggering ball; the step's own pitch is applied on top
+    pub fn play_slice_array(&mut self, array_name: &str, rate: f64, pan: f32) -> Result<(), AudioError> {
         // First, extract all the needed values without holding mutable references
-        let (sample_key, current_marker_index, sequence_len) = {
+        let (sample_key, current_marker_index, sequence_len, rate) = {
             let slice_array = self.slice_arrays.get(array_name)
                 .ok_or_else(|| AudioError::PlaybackError(format!("Slice array not found: {}", array_name)))?;
             
@@ -208,29 +437,35 @@ impl AudioEngine {
             }
             
             let current_marker_index = slice_array.sequence[slice_array.current_index];
-            (slice_array.sample_key.clone(), current_marker_index, slice_array.sequence.len())
+            let semitones = if slice_array.pitches.is_empty() {
+                0.0
+            } else {
+                slice_array.pitches[slice_array.current_index % slice_array.pitches.len()]
+            };
+            (slice_array.sample_key.clone(), current_marker_index, slice_array.sequence.len(), rate * semitones_to_rate(semitones))
         };
         
-        // Get the sample
-        let sample = self.samples.get(&sample_key)
-            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
+        // Get the sample's markers
+        let slice_markers = self.samples.get(&sample_key)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?
+            .slice_markers.clone();
         
         println!("Debug: play_slice_array - Sample '{}' has {} markers: {:?}", 
-                 sample_key, sample.slice_markers.len(), sample.slice_markers);
+                 sample_key, slice_markers.len(), slice_markers);
         
         // If no markers are set, play the whole sample
-        if sample.slice_markers.is_empty() {
+        if slice_markers.is_empty() {
             println!("Debug: No markers found, playing entire sample");
-            self.play_sample(&sample_key)?;
+            self.play_sample_with_settings(&sample_key, 1.0, rate, pan)?;
         } else {
             // Validate marker index
-            if current_marker_index >= sample.slice_markers.len() {
+            if current_marker_index >= slice_markers.len() {
                 return Err(AudioError::PlaybackError(format!("Invalid marker index: {}", current_marker_index)));
             }
             
-            let start_time = sample.slice_markers[current_marker_index];
-            let end_time = if current_marker_index + 1 < sample.slice_markers.len() {
-                sample.slice_markers[current_marker_index + 1]
+            let start_time = slice_markers[current_marker_index];
+            let end_time = if current_marker_index + 1 < slice_markers.len() {
+                slice_markers[current_marker_index + 1]
             } else {
                 // Play to the end of the sample
                 f64::INFINITY
@@ -241,7 +476,7 @@ impl AudioEngine {
                      if end_time == f64::INFINITY { -1.0 } else { end_time });
             
             // Play the slice from start_time to end_time
-            self.play_sample_slice(&sample_key, start_time, end_time)?;
+            self.trigger_voice(&sample_key, start_time, end_time, 1.0, rate, pan)?;
         }
         
         // Now update the slice array's current index
@@ -252,46 +487,239 @@ impl AudioEngine {
         Ok(())
     }
     
-    pub fn play_sample_slice_public(&self, sample_key: &str, start_time: f64, end_time: f64) -> Result<(), AudioError> {
+    pub fn play_sample_slice_public(&mut self, sample_key: &str, start_time: f64, end_time: f64) -> Result<(), AudioError> {
         self.play_sample_slice(sample_key, start_time, end_time)
     }
 
-    fn play_sample_slice(&self, sample_key: &str, start_time: f64, end_time: f64) -> Result<(), AudioError> {
-        let sample = self.samples.get(sample_key)
-            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
-        
-        let cursor = std::io::Cursor::new(sample.data.as_ref().clone());
-        let mut decoder = Decoder::new(cursor)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to decode sample: {}", e)))?;
+    fn play_sample_slice(&mut self, sample_key: &str, start_time: f64, end_time: f64) -> Result<(), AudioError> {
+        self.trigger_voice(sample_key, start_time, end_time, 1.0, 1.0, 0.0)
+    }
+    
+    // Schedule part of a sample (times in seconds, end may be INFINITY) on the mixer
+    fn trigger_voice(&mut self, sample_key: &str, start_time: f64, end_time: f64, volume: f32, rate: f64, pan: f32) -> Result<(), AudioError> {
+        if self.find_sample(sample_key).is_none() {
+            return Err(AudioError::PlaybackError(format!("Sample not found: {}", sample_key)));
+        }
         
-        // Skip to start time (approximate)
-        let sample_rate = decoder.sample_rate() as f64;
-        let channels = decoder.channels() as f64;
-        let samples_to_skip = (start_time * sample_rate * channels) as usize;
+        if self.capture_voice(sample_key, start_time, end_time, volume, rate, pan) {
+            return Ok(());
+        }
         
-        // Create a new decoder and skip samples
-        let cursor = std::io::Cursor::new(sample.data.as_ref().clone());
-        let decoder = Decoder::new(cursor)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to decode sample: {}", e)))?;
+        let mixer = match self.mixer.clone() {
+            Some(mixer) => mixer,
+            None => return Ok(()), // Headless: nothing to play to
+        };
         
-        let skipped_decoder = decoder.skip_duration(std::time::Duration::from_secs_f64(start_time));
+        let bus = self.trigger_bus.as_ref().map(|(bus, _)| *bus);
+        let voice = self.build_voice(sample_key, start_time, end_time, volume, rate, pan)?.with_bus(bus);
+        let start_frame = self.schedule_frame(&mixer);
+        mixer.schedule(voice, start_frame);
         
-        // If we have an end time, take only the duration we need
-        let final_decoder = if end_time != f64::INFINITY {
-            let duration = end_time - start_time;
-            Box::new(skipped_decoder.take_duration(std::time::Duration::from_secs_f64(duration))) as Box<dyn Source<Item = i16> + Send>
-        } else {
-            Box::new(skipped_decoder) as Box<dyn Source<Item = i16> + Send>
+        Ok(())
+    }
+    
+    fn build_voice(&self, sample_key: &str, start_time: f64, end_time: f64, volume: f32, rate: f64, pan: f32) -> Result<Voice, AudioError> {
+        let sample = self.find_sample(sample_key)
+            .ok_or_else(|| AudioError::PlaybackError(format!("Sample not found: {}", sample_key)))?;
+        let (start, end) = sample.frame_range(start_time, end_time);
+        Ok(Voice::new(Arc::clone(&sample.pcm), sample.channels, sample.sample_rate, start, end, volume, rate).with_pan(pan))
+    }
+    
+    // Mixer frame at which a sound triggered now should start. Sounds from the
+    // simulation keep their exact spacing relative to each other; everything
+    // else plays as soon as possible.
+    fn schedule_frame(&mut self, mixer: &Mixer) -> u64 {
+        let rate = MIXER_SAMPLE_RATE as f64;
+        let current = mixer.current_frame();
+        let earliest = current + (SCHEDULE_LATENCY * rate) as u64;
+        
+        let time = match self.trigger_time {
+            Some(time) => time,
+            None => return earliest,
         };
         
-        let sink = Sink::try_new(&self.stream_handle)
-            .map_err(|e| AudioError::PlaybackError(format!("Failed to create sink: {}", e)))?;
+        // The drift check uses the collision itself; quantizing may push the start further ahead
+        let delay = (self.trigger_delay * rate).round() as u64;
+        if let Some((anchor_time, anchor_frame)) = self.timeline_anchor {
+            let frame = anchor_frame as f64 + (time - anchor_time) * rate;
+            if frame >= current as f64 && frame <= earliest as f64 + MAX_SCHEDULE_AHEAD * rate {
+                return frame.round() as u64 + delay;
+            }
+        }
+        
+        // First sound of a run, or the simulation drifted from the audio clock: re-anchor
+        self.timeline_anchor = Some((time, earliest));
+        earliest + delay
+    }
+    
+    // `start_time` is when the sounds should start, at or after `time` when quantizing
+    pub fn set_trigger_time(&mut self, time: Option<f64>, start_time: f64) {
+        self.trigger_time = time;
+        self.trigger_delay = time.map_or(0.0, |time| (start_time - time).max(0.0));
+    }
+    
+    // Records the sound instead of playing it when an offline render is running.
+    // Returns true if the sound was captured.
+    fn capture_voice(&self, sample_key: &str, start_time: f64, end_time: f64, volume: f32, rate: f64, pan: f32) -> bool {
+        if let Some(ref mut voices) = *self.render_capture.borrow_mut() {
+            voices.push(CapturedVoice {
+                sample_key: sample_key.to_string(),
+                time: self.trigger_time.map_or(0.0, |time| time + self.trigger_delay),
+                start_time,
+                end_time,
+                volume,
+                rate,
+                pan,
+                bus: self.trigger_bus.as_ref().map(|(bus, _)| *bus),
+                effects: self.trigger_bus.as_ref().map(|(_, effects)| effects.clone()).unwrap_or_default(),
+            });
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// Route sounds triggered next through `bus` (a ball id) with `effects`, or
+    /// straight to the master bus when `bus` is None.
+    pub fn set_trigger_bus(&mut self, bus: Option<u32>, effects: &[EffectSettings]) {
+        if let (Some(bus), Some(mixer)) = (bus, &self.mixer) {
+            mixer.set_bus_effects(bus, effects);
+        }
+        self.trigger_bus = bus.map(|bus| (bus, effects.to_vec()));
+    }
+    
+    pub fn set_master_effects(&mut self, effects: Vec<EffectSettings>) {
+        if let Some(ref mixer) = self.mixer {
+            mixer.set_master_effects(&effects);
+        }
+        self.master_effects = effects;
+    }
+    
+    // Drop the effect bus of a deleted ball, or of every ball when `bus` is None,
+    // so chains of balls that no longer exist stop running in the mixer
+    pub fn remove_effect_bus(&mut self, bus: Option<u32>) {
+        if let Some(ref mixer) = self.mixer {
+            match bus {
+                Some(bus) => mixer.set_bus_effects(bus, &[]),
+                None => mixer.clear_buses(),
+            }
+        }
+    }
+    
+    pub fn begin_render_capture(&mut self) {
+        self.set_trigger_time(None, 0.0);
+        self.trigger_bus = None;
+        *self.render_capture.borrow_mut() = Some(Vec::new());
+    }
+    
+    pub fn end_render_capture(&mut self) -> Vec<CapturedVoice> {
+        self.render_capture.borrow_mut().take().unwrap_or_default()
+    }
+    
+    // Mix captured voices into a WAV file that is `seconds` long, using the same
+    // mixer (scheduling and polyphony) as live playback
+    pub fn render_voices_to_wav<P: AsRef<Path>>(&self, path: P, voices: &[CapturedVoice], seconds: f64) -> Result<(), AudioError> {
+        if !seconds.is_finite() || seconds < 0.0 || seconds > MAX_RENDER_SECONDS {
+            return Err(AudioError::PlaybackError(format!("Render length must be between 0 and {} seconds", MAX_RENDER_SECONDS)));
+        }
+        let channels = RENDER_CHANNELS as usize;
+        let rate = RENDER_SAMPLE_RATE as f64;
+        let total_frames = (seconds * rate).round() as usize;
+        let mut mix = vec![0.0f32; total_frames * channels];
+        
+        let mut ordered: Vec<&CapturedVoice> = voices.iter().collect();
+        ordered.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(std::cmp::Ordering::Equal));
+        
+        let mut state = MixerState::new();
+        state.set_polyphony(self.polyphony);
+        state.set_master_effects(&self.master_effects);
+        
+        for captured in ordered {
+            let voice = match self.build_voice(&captured.sample_key, captured.start_time, captured.end_time, captured.volume, captured.rate, captured.pan) {
+                Ok(voice) => voice.with_bus(captured.bus),
+                Err(e) => {
+                    log::warn!("Skipping voice {} in render: {}", captured.sample_key, e);
+                    continue;
+                }
+            };
+            
+            // Render up to the voice's start so polyphony limits apply in time order
+            let start_frame = ((captured.time * rate).round() as usize).min(total_frames);
+            let rendered = state.current_frame() as usize;
+            if start_frame > rendered {
+                state.render(&mut mix[rendered * channels..start_frame * channels]);
+            }
+            
+            if let Some(bus) = captured.bus {
+                state.set_bus_effects(bus, &captured.effects);
+            }
+            state.schedule(voice, start_frame as u64);
+        }
         
-        sink.append(final_decoder);
-        sink.detach();
+        let rendered = state.current_frame() as usize;
+        if total_frames > rendered {
+            state.render(&mut mix[rendered * channels..]);
+        }
         
-        Ok(())
+        write_wav_file(path, &mix, RENDER_SAMPLE_RATE, RENDER_CHANNELS)
+    }
+}
+
+// Markers from the sample's sidecar file, if it has one saved for this exact audio
+fn load_saved_markers(sample_path: &str, sample_rate: u32, content_hash: u64) -> Vec<f64> {
+    match MarkerFile::load(&project::markers_path(sample_path)) {
+        Ok(Some(saved)) if saved.matches(sample_rate, content_hash) => saved.slice_markers,
+        Ok(Some(_)) => {
+            log::warn!("Ignoring saved slice markers for {}: the file has changed since they were saved", sample_path);
+            Vec::new()
+        },
+        Ok(None) => Vec::new(),
+        Err(e) => {
+            log::warn!("{}", e);
+            Vec::new()
+        },
+    }
+}
+
+// Playback rate that shifts pitch by `semitones` (+12 = an octave up, twice as fast)
+pub fn semitones_to_rate(semitones: f64) -> f64 {
+    2f64.powf(semitones / 12.0)
+}
+
+// Write interleaved f32 samples as a 16-bit PCM WAV file
+pub fn write_wav_file<P: AsRef<Path>>(path: P, samples: &[f32], sample_rate: u32, channels: u16) -> Result<(), AudioError> {
+    use std::io::Write;
+    
+    let path = path.as_ref();
+    let data_len = (samples.len() * 2) as u32;
+    let block_align = channels * 2;
+    let byte_rate = sample_rate * block_align as u32;
+    
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&byte_rate.to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&value.to_le_bytes());
     }
+    
+    let mut file = File::create(path)
+        .map_err(|e| AudioError::PlaybackError(format!("Cannot create {}: {}", path.display(), e)))?;
+    file.write_all(&bytes)
+        .map_err(|e| AudioError::PlaybackError(format!("Cannot write {}: {}", path.display(), e)))?;
+    
+    Ok(())
 }
 
 // Helper functions to work with the thread-local audio engine
@@ -313,12 +741,92 @@ where
     })
 }
 
-pub fn play_audio_sample(sample_key: &str, volume: f32) -> Result<(), AudioError> {
+// Install a headless audio engine for this thread so no output device is opened
+pub fn init_headless_audio() {
+    AUDIO_ENGINE.with(|engine_cell| {
+        *engine_cell.borrow_mut() = Some(AudioEngine::new_headless());
+    });
+}
+
+// Set the simulation time for sounds triggered next and the time they start
+// at (later when quantized). Does nothing if audio hasn't been initialised
+// yet, so scenes without samples never open a device.
+pub fn set_trigger_time(time: f64, start_time: f64) {
+    AUDIO_ENGINE.with(|engine_cell| {
+        if let Some(ref mut engine) = *engine_cell.borrow_mut() {
+            engine.set_trigger_time(Some(time), start_time);
+        }
+    });
+}
+
+// Sounds triggered after this (console commands, editor previews) play immediately
+pub fn clear_trigger_time() {
+    AUDIO_ENGINE.with(|engine_cell| {
+        if let Some(ref mut engine) = *engine_cell.borrow_mut() {
+            engine.set_trigger_time(None, 0.0);
+            engine.set_trigger_bus(None, &[]);
+        }
+    });
+}
+
+// Send sounds triggered next through a ball's effects. Like set_trigger_time,
+// does nothing before audio is initialised.
+pub fn set_trigger_bus(bus: Option<u32>, effects: &[EffectSettings]) {
+    AUDIO_ENGINE.with(|engine_cell| {
+        if let Some(ref mut engine) = *engine_cell.borrow_mut() {
+            engine.set_trigger_bus(bus, effects);
+        }
+    });
+}
+
+// Like set_trigger_bus, does nothing before audio is initialised
+pub fn remove_effect_bus(bus: Option<u32>) {
+    AUDIO_ENGINE.with(|engine_cell| {
+        if let Some(ref mut engine) = *engine_cell.borrow_mut() {
+            engine.remove_effect_bus(bus);
+        }
+    });
+}
+
+pub fn set_master_effects(effects: Vec<EffectSettings>) -> Result<(), AudioError> {
+    with_audio_engine(|engine| {
+        engine.set_master_effects(effects);
+        Ok(())
+    })
+}
+
+pub fn set_polyphony(polyphony: usize) -> Result<(), AudioError> {
+    with_audio_engine(|engine| {
+        engine.set_polyphony(polyphony);
+        Ok(())
+    })
+}
+
+pub fn begin_render_capture() -> Result<(), AudioError> {
+    with_audio_engine(|engine| {
+        engine.begin_render_capture();
+        Ok(())
+    })
+}
+
+pub fn end_render_capture() -> Result<Vec<CapturedVoice>, AudioError> {
+    with_audio_engine(|engine| Ok(engine.end_render_capture()))
+}
+
+pub fn render_voices_to_wav<P: AsRef<Path>>(path: P, voices: &[CapturedVoice], seconds: f64) -> Result<(), AudioError> {
+    with_audio_engine(|engine| engine.render_voices_to_wav(path, voices, seconds))
+}
+
+pub fn play_audio_sample(sample_key: &str, volume: f32, rate: f64, pan: f32) -> Result<(), AudioError> {
     with_audio_engine(|engine| {
-        engine.play_sample_with_volume(sample_key, volume)
+        engine.play_sample_with_settings(sample_key, volume, rate, pan)
     })
 }
 
+pub fn play_synth_note(settings: &SynthSettings, frequency: f64, volume: f32, rate: f64, pan: f32) -> Result<(), AudioError> {
+    with_audio_engine(|engine| engine.play_synth_note(settings, frequency, volume, rate, pan))
+}
+
 pub fn load_audio_file<P: AsRef<Path>>(file_path: P) -> Result<String, AudioError> {
     with_audio_engine(|engine| {
         engine.load_audio_file(file_path)
@@ -331,12 +839,20 @@ pub fn create_slice_array(name: String, sample_key: String, sequence: Vec<usize>
     })
 }
 
+pub fn set_slice_pitches(name: &str, pitches: Vec<f64>) -> Result<(), AudioError> {
+    with_audio_engine(|engine| engine.set_slice_pitches(name, pitches))
+}
+
 pub fn set_sample_markers(sample_key: &str, markers: Vec<f64>) -> Result<(), AudioError> {
     with_audio_engine(|engine| {
         engine.set_sample_markers(sample_key, markers)
     })
 }
 
+pub fn save_sample_markers(sample_key: &str) -> Result<String, AudioError> {
+    with_audio_engine(|engine| engine.save_sample_markers(sample_key))
+}
+
 pub fn get_sample_markers(sample_key: &str) -> Result<Vec<f64>, AudioError> {
     with_audio_engine(|engine| {
         engine.get_sample_markers(sample_key)
@@ -347,8 +863,91 @@ pub fn get_sample_duration(sample_key: &str) -> Result<f64, AudioError> {
     with_audio_engine(|engine| engine.get_sample_duration(sample_key))
 }
 
-pub fn play_slice_array(array_name: &str) -> Result<(), AudioError> {
+pub fn get_sample_frames(sample_key: &str) -> Result<(usize, u32), AudioError> {
+    with_audio_engine(|engine| engine.get_sample_frames(sample_key))
+}
+
+pub fn find_sample_key(name: &str) -> Result<Option<String>, AudioError> {
+    with_audio_engine(|engine| Ok(engine.find_sample_key(name)))
+}
+
+pub fn export_slices<P: AsRef<Path>>(sample_key: &str, directory: P) -> Result<Vec<String>, AudioError> {
+    with_audio_engine(|engine| engine.export_slices(sample_key, directory.as_ref()))
+}
+
+pub fn play_slice_array(array_name: &str, rate: f64, pan: f32) -> Result<(), AudioError> {
     with_audio_engine(|engine| {
-        engine.play_slice_array(array_name)
+        engine.play_slice_array(array_name, rate, pan)
     })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One second of stereo at 100 Hz, sliced at the given times
+    fn sample(slice_markers: Vec<f64>) -> AudioSample {
+        AudioSample {
+            pcm: Arc::new(vec![0.25; 200]),
+            sample_rate: 100,
+            channels: 2,
+            file_path: "samples/loop.wav".to_string(),
+            slice_markers,
+            content_hash: 0,
+        }
+    }
+
+    fn scratch_dir(test: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("cant_export_{}_{}", test, std::process::id()));
+        std::fs::remove_dir_all(&directory).ok();
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn files_in(directory: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn empty_slices_leave_no_gaps_in_the_numbering() {
+        let directory = scratch_dir("gaps");
+        let written = sample(vec![0.0, 0.5, 0.5, 0.75]).export_slices(&directory).unwrap();
+        assert_eq!(written.len(), 3);
+        assert_eq!(files_in(&directory), vec!["loop.wav.slices.json", "loop_01.wav", "loop_02.wav", "loop_03.wav"]);
+        // 44-byte header plus 16-bit stereo frames: 50, 25 and 25 frames
+        let sizes: Vec<u64> = written.iter().map(|path| std::fs::metadata(path).unwrap().len()).collect();
+        assert_eq!(sizes, vec![44 + 200, 44 + 100, 44 + 100]);
+        std::fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn a_new_export_replaces_the_old_set_only() {
+        let directory = scratch_dir("replace");
+        sample(vec![0.0, 0.2, 0.4, 0.6, 0.8]).export_slices(&directory).unwrap();
+        for other in ["loop.wav", "loop_1.wav", "loop_take.wav", "loops_01.wav"] {
+            std::fs::write(directory.join(other), b"keep").unwrap();
+        }
+
+        sample(vec![0.0, 0.5]).export_slices(&directory).unwrap();
+        assert_eq!(files_in(&directory), vec![
+            "loop.wav", "loop.wav.slices.json", "loop_01.wav", "loop_02.wav", "loop_1.wav", "loop_take.wav", "loops_01.wav",
+        ]);
+        std::fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn files_the_export_did_not_write_survive_a_re_export() {
+        let directory = scratch_dir("keep");
+        std::fs::write(directory.join("loop_03.wav"), b"hand-made").unwrap();
+        sample(vec![0.0, 0.5]).export_slices(&directory).unwrap();
+        sample(vec![0.0, 0.5]).export_slices(&directory).unwrap();
+
+        assert_eq!(files_in(&directory), vec!["loop.wav.slices.json", "loop_01.wav", "loop_02.wav", "loop_03.wav"]);
+        assert_eq!(std::fs::read(directory.join("loop_03.wav")).unwrap(), b"hand-made");
+        std::fs::remove_dir_all(&directory).ok();
+    }
 }
\ No newline at end of file
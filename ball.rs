@@ -1,11 +1,18 @@
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::collections::HashMap;
+use crate::synth::SynthSettings;
+use crate::effects::EffectSettings;
 
 // Start ball IDs from 1000 to avoid conflicts with squares
 static NEXT_BALL_ID: AtomicU32 = AtomicU32::new(1000);
 // Sequential counter for friendly naming (ball1, ball2, etc.)
 static BALL_SEQUENCE: AtomicU32 = AtomicU32::new(1);
 
+// Four octaves either way; past that resampling is mostly aliasing or silence
+const MAX_PITCH_SEMITONES: f64 = 48.0;
+const MIN_PLAYBACK_RATE: f64 = 0.0625;
+const MAX_PLAYBACK_RATE: f64 = 16.0;
+
 #[derive(Debug, Clone)]
 pub struct Ball {
     pub id: u32,
@@ -19,6 +26,12 @@ pub struct Ball {
     pub script: Option<String>, // script to execute on collision
     pub audio_file: Option<String>, // path to audio file
     pub audio_volume: f32, // volume level (0.0 to 1.0)
+    pub pitch: f64, // semitones added to the sample's pitch
+    pub playback_rate: f64, // speed multiplier applied on top of the pitch
+    pub pan: Option<f64>, // fixed stereo position (-1.0 left to 1.0 right); None follows the pan mode
+    pub synth: Option<SynthSettings>, // plays a synth note instead of the sample when set
+    pub effects: Vec<EffectSettings>, // effect chain this ball's sounds go through, in order
+    pub midi_note: Option<u8>, // note recorded by midi_record; a square's own note wins on square hits
     pub color: String, // New: store the color as a string
     pub hit_counts: HashMap<u32, u32>, // object_id -> hit_count
 }
@@ -40,6 +53,12 @@ impl Ball {
             script: None,
             audio_file: None,
             audio_volume: 1.0,
+            pitch: 0.0,
+            playback_rate: 1.0,
+            pan: None,
+            synth: None,
+            effects: Vec::new(),
+            midi_note: None,
             color: "white".to_string(), // Default color
             hit_counts: HashMap::new(),
         }
@@ -57,10 +76,24 @@ impl Ball {
         self.hit_counts.values().sum()
     }
     
+    pub fn set_script(&mut self, script: String) {
+        self.script = Some(script);
+    }
+    
+    pub fn get_script(&self) -> Option<&str> {
+        self.script.as_deref()
+    }
+    
     pub fn get_friendly_name(&self) -> String {
         format!("ball{}", self.sequence_number)
     }
     
+    // Give a restored ball its old name and keep new balls from reusing it
+    pub fn set_sequence_number(&mut self, sequence_number: u32) {
+        self.sequence_number = sequence_number;
+        BALL_SEQUENCE.fetch_max(sequence_number + 1, Ordering::SeqCst);
+    }
+    
     pub fn update_physics(&mut self, dt: f64) {
         self.x += self.velocity_x * dt;
         self.y += self.velocity_y * dt;
@@ -115,9 +148,31 @@ impl Ball {
         self.audio_volume = volume.clamp(0.0, 1.0);
     }
     
-    pub fn play_collision_audio(&self) {
-        if let Some(ref audio_file) = self.audio_file {
-            if let Err(e) = crate::audio_engine::play_audio_sample(audio_file, self.audio_volume) {
+    pub fn set_pitch(&mut self, semitones: f64) {
+        self.pitch = semitones.clamp(-MAX_PITCH_SEMITONES, MAX_PITCH_SEMITONES);
+    }
+    
+    pub fn set_playback_rate(&mut self, rate: f64) {
+        self.playback_rate = rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE);
+    }
+    
+    pub fn set_pan(&mut self, pan: Option<f64>) {
+        self.pan = pan.map(|pan| pan.clamp(-1.0, 1.0));
+    }
+    
+    // Resampling ratio for this ball's sounds: rate and pitch combined
+    pub fn get_effective_rate(&self) -> f64 {
+        self.playback_rate * crate::audio_engine::semitones_to_rate(self.pitch)
+    }
+    
+    pub fn play_collision_audio(&self, pan: f32) {
+        if let Some(ref synth) = self.synth {
+            let frequency = synth.frequency * crate::audio_engine::semitones_to_rate(self.pitch);
+            if let Err(e) = crate::audio_engine::play_synth_note(synth, frequency, self.audio_volume, self.playback_rate, pan) {
+                log::warn!("Failed to play synth for {}: {}", self.get_friendly_name(), e);
+            }
+        } else if let Some(ref audio_file) = self.audio_file {
+            if let Err(e) = crate::audio_engine::play_audio_sample(audio_file, self.audio_volume, self.get_effective_rate(), pan) {
                 log::warn!("Failed to play audio for {}: {}", self.get_friendly_name(), e);
             }
         }
@@ -126,6 +181,7 @@ impl Ball {
     pub fn load_audio_file<P: AsRef<std::path::Path>>(&mut self, file_path: P) -> Result<(), crate::audio_engine::AudioError> {
         let sample_key = crate::audio_engine::load_audio_file(&file_path)?;
         self.audio_file = Some(sample_key);
+        self.synth = None; // Loading a sample replaces the synth
         Ok(())
     }
     
@@ -0,0 +1,397 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// Longest delay time in seconds; sets the size of the delay buffer
+const MAX_DELAY_TIME: f32 = 2.0;
+// Kept below 1.0 so a delay can never feed back forever
+const MAX_FEEDBACK: f32 = 0.95;
+// Comb and allpass lengths in samples at 44.1 kHz (from Freeverb); the right
+// channel uses slightly longer lines so the tail is stereo
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+const STEREO_SPREAD: usize = 23;
+
+// What an effect does, as set by `fx` and stored in projects
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EffectSettings {
+    Lowpass { cutoff: f32, resonance: f32 }, // Cutoff in Hz, resonance as filter Q
+    Highpass { cutoff: f32, resonance: f32 },
+    Delay { time: f32, feedback: f32, mix: f32 }, // Time in seconds
+    Reverb { size: f32, mix: f32 },              // Both 0.0 - 1.0
+}
+
+impl EffectSettings {
+    // Effects of the same kind replace each other in a chain
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EffectSettings::Lowpass { .. } => "lowpass",
+            EffectSettings::Highpass { .. } => "highpass",
+            EffectSettings::Delay { .. } => "delay",
+            EffectSettings::Reverb { .. } => "reverb",
+        }
+    }
+
+    /// Build settings from an `fx` command: the effect name and up to three numbers.
+    /// Missing numbers fall back to defaults; a cutoff or delay time must be above 0.
+    pub fn from_command(kind: &str, values: &[f64]) -> Result<Self, String> {
+        let value = |index: usize, default: f32| values.get(index).map_or(default, |&v| v as f32);
+        let expected = match kind {
+            "lowpass" | "highpass" | "reverb" => 2,
+            "delay" => 3,
+            _ => return Err(format!("Unknown effect '{}': use lowpass, highpass, delay or reverb", kind)),
+        };
+        if values.len() > expected {
+            return Err(format!("{} takes at most {} numbers", kind, expected));
+        }
+        if values.first().is_some_and(|&first| first <= 0.0) && kind != "reverb" {
+            let setting = if kind == "delay" { "delay time" } else { "cutoff" };
+            return Err(format!("{} {} must be greater than 0", kind, setting));
+        }
+
+        Ok(match kind {
+            "lowpass" => EffectSettings::Lowpass { cutoff: value(0, 1000.0).clamp(20.0, 20000.0), resonance: value(1, 0.707).clamp(0.1, 20.0) },
+            "highpass" => EffectSettings::Highpass { cutoff: value(0, 200.0).clamp(20.0, 20000.0), resonance: value(1, 0.707).clamp(0.1, 20.0) },
+            "delay" => EffectSettings::Delay {
+                time: value(0, 0.25).clamp(0.001, MAX_DELAY_TIME),
+                feedback: value(1, 0.4).clamp(0.0, MAX_FEEDBACK),
+                mix: value(2, 0.5).clamp(0.0, 1.0),
+            },
+            _ => EffectSettings::Reverb { size: value(0, 0.5).clamp(0.0, 1.0), mix: value(1, 0.3).clamp(0.0, 1.0) },
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            EffectSettings::Lowpass { cutoff, resonance } => format!("lowpass {} Hz (q {})", cutoff, resonance),
+            EffectSettings::Highpass { cutoff, resonance } => format!("highpass {} Hz (q {})", cutoff, resonance),
+            EffectSettings::Delay { time, feedback, mix } => format!("delay {}s, feedback {}, mix {}", time, feedback, mix),
+            EffectSettings::Reverb { size, mix } => format!("reverb size {}, mix {}", size, mix),
+        }
+    }
+}
+
+// Biquad filter (RBJ cookbook) in transposed direct form II, one state per channel
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    state: [[f32; 2]; 2],
+}
+
+impl Biquad {
+    fn new() -> Self {
+        Self { b0: 1.0, b1: 0.0, b2: 0.0, a1: 0.0, a2: 0.0, state: [[0.0; 2]; 2] }
+    }
+
+    fn configure(&mut self, highpass: bool, cutoff: f32, resonance: f32, sample_rate: f32) {
+        let omega = 2.0 * PI * cutoff.min(sample_rate * 0.45) / sample_rate;
+        let alpha = omega.sin() / (2.0 * resonance);
+        let cos = omega.cos();
+        let a0 = 1.0 + alpha;
+        let (b0, b1) = if highpass {
+            ((1.0 + cos) / 2.0, -(1.0 + cos))
+        } else {
+            ((1.0 - cos) / 2.0, 1.0 - cos)
+        };
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b0 / a0;
+        self.a1 = -2.0 * cos / a0;
+        self.a2 = (1.0 - alpha) / a0;
+    }
+
+    fn process(&mut self, input: f32, channel: usize) -> f32 {
+        let state = &mut self.state[channel];
+        let output = self.b0 * input + state[0];
+        state[0] = self.b1 * input - self.a1 * output + state[1];
+        state[1] = self.b2 * input - self.a2 * output;
+        output
+    }
+}
+
+struct Delay {
+    buffer: Vec<[f32; 2]>,
+    position: usize,
+    length: usize,
+    feedback: f32,
+    mix: f32,
+}
+
+impl Delay {
+    fn new(sample_rate: f32) -> Self {
+        Self {
+            buffer: vec![[0.0; 2]; (MAX_DELAY_TIME * sample_rate) as usize + 1],
+            position: 0,
+            length: 1,
+            feedback: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    fn configure(&mut self, time: f32, feedback: f32, mix: f32, sample_rate: f32) {
+        self.length = ((time * sample_rate) as usize).clamp(1, self.buffer.len());
+        self.feedback = feedback;
+        self.mix = mix;
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        let read = (self.position + self.buffer.len() - self.length) % self.buffer.len();
+        let delayed = self.buffer[read];
+        self.buffer[self.position] = [
+            frame[0] + delayed[0] * self.feedback,
+            frame[1] + delayed[1] * self.feedback,
+        ];
+        self.position = (self.position + 1) % self.buffer.len();
+        [frame[0] + delayed[0] * self.mix, frame[1] + delayed[1] * self.mix]
+    }
+}
+
+// Feedback comb filter with a one-pole lowpass in the loop, as in Freeverb
+struct Comb {
+    buffer: Vec<f32>,
+    position: usize,
+    filter_state: f32,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Self { buffer: vec![0.0; length], position: 0, filter_state: 0.0 }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.position];
+        self.filter_state = output * (1.0 - damping) + self.filter_state * damping;
+        self.buffer[self.position] = input + self.filter_state * feedback;
+        self.position = (self.position + 1) % self.buffer.len();
+        output
+    }
+}
+
+struct Allpass {
+    buffer: Vec<f32>,
+    position: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Self { buffer: vec![0.0; length], position: 0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = input + delayed * 0.5;
+        self.position = (self.position + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+// Schroeder-style reverb: parallel combs into series allpasses, per channel
+struct Reverb {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    feedback: f32,
+    mix: f32,
+}
+
+impl Reverb {
+    fn new(sample_rate: f32) -> Self {
+        let scale = sample_rate / 44100.0;
+        let length = |base: usize, spread: usize| (((base + spread) as f32 * scale) as usize).max(1);
+        let channel = |spread: usize| -> (Vec<Comb>, Vec<Allpass>) {
+            (
+                COMB_LENGTHS.iter().map(|&base| Comb::new(length(base, spread))).collect(),
+                ALLPASS_LENGTHS.iter().map(|&base| Allpass::new(length(base, spread))).collect(),
+            )
+        };
+        let (left_combs, left_allpasses) = channel(0);
+        let (right_combs, right_allpasses) = channel(STEREO_SPREAD);
+        Self {
+            combs: [left_combs, right_combs],
+            allpasses: [left_allpasses, right_allpasses],
+            feedback: 0.0,
+            mix: 0.0,
+        }
+    }
+
+    fn configure(&mut self, size: f32, mix: f32) {
+        self.feedback = 0.7 + size * 0.28;
+        self.mix = mix;
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        // Both channels feed the same mono input into their own tanks
+        let input = (frame[0] + frame[1]) * 0.5 * 0.25;
+        let mut output = [0.0; 2];
+        for channel in 0..2 {
+            let mut wet: f32 = self.combs[channel].iter_mut()
+                .map(|comb| comb.process(input, self.feedback, 0.2))
+                .sum();
+            for allpass in &mut self.allpasses[channel] {
+                wet = allpass.process(wet);
+            }
+            output[channel] = frame[channel] * (1.0 - self.mix) + wet * self.mix;
+        }
+        output
+    }
+}
+
+enum Processor {
+    Filter(Biquad),
+    Delay(Delay),
+    Reverb(Reverb),
+}
+
+struct Effect {
+    settings: EffectSettings,
+    processor: Processor,
+}
+
+impl Effect {
+    fn new(settings: EffectSettings, sample_rate: f32) -> Self {
+        let processor = match settings {
+            EffectSettings::Lowpass { .. } | EffectSettings::Highpass { .. } => Processor::Filter(Biquad::new()),
+            EffectSettings::Delay { .. } => Processor::Delay(Delay::new(sample_rate)),
+            EffectSettings::Reverb { .. } => Processor::Reverb(Reverb::new(sample_rate)),
+        };
+        let mut effect = Self { settings: settings.clone(), processor };
+        effect.configure(settings, sample_rate);
+        effect
+    }
+
+    // New parameters keep the running state, so a delay tail survives a change made on a hit
+    fn configure(&mut self, settings: EffectSettings, sample_rate: f32) {
+        match (&settings, &mut self.processor) {
+            (EffectSettings::Lowpass { cutoff, resonance }, Processor::Filter(filter)) => filter.configure(false, *cutoff, *resonance, sample_rate),
+            (EffectSettings::Highpass { cutoff, resonance }, Processor::Filter(filter)) => filter.configure(true, *cutoff, *resonance, sample_rate),
+            (EffectSettings::Delay { time, feedback, mix }, Processor::Delay(delay)) => delay.configure(*time, *feedback, *mix, sample_rate),
+            (EffectSettings::Reverb { size, mix }, Processor::Reverb(reverb)) => reverb.configure(*size, *mix),
+            _ => {
+                *self = Effect::new(settings, sample_rate);
+                return;
+            },
+        }
+        self.settings = settings;
+    }
+
+    fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+        match &mut self.processor {
+            Processor::Filter(filter) => [filter.process(frame[0], 0), filter.process(frame[1], 1)],
+            Processor::Delay(delay) => delay.process(frame),
+            Processor::Reverb(reverb) => reverb.process(frame),
+        }
+    }
+}
+
+/// Effects applied in order to a stereo bus (one ball, or the master output).
+pub struct EffectChain {
+    effects: Vec<Effect>,
+    sample_rate: f32,
+}
+
+impl EffectChain {
+    pub fn new(sample_rate: u32) -> Self {
+        Self { effects: Vec::new(), sample_rate: sample_rate as f32 }
+    }
+
+    /// Match the chain to `settings`, keeping the state of effects that are still there.
+    pub fn update(&mut self, settings: &[EffectSettings]) {
+        let sample_rate = self.sample_rate;
+        let mut previous = std::mem::take(&mut self.effects);
+        for wanted in settings {
+            match previous.iter().position(|effect| effect.settings.kind() == wanted.kind()) {
+                Some(index) => {
+                    let mut effect = previous.remove(index);
+                    if effect.settings != *wanted {
+                        effect.configure(wanted.clone(), sample_rate);
+                    }
+                    self.effects.push(effect);
+                },
+                None => self.effects.push(Effect::new(wanted.clone(), sample_rate)),
+            }
+        }
+    }
+
+    // Process interleaved stereo in place
+    pub fn process(&mut self, buffer: &mut [f32]) {
+        if self.effects.is_empty() {
+            return;
+        }
+        for frame in buffer.chunks_exact_mut(2) {
+            let mut value = [frame[0], frame[1]];
+            for effect in &mut self.effects {
+                value = effect.process(value);
+            }
+            frame[0] = value[0];
+            frame[1] = value[1];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lowpass_passes_dc_and_removes_nyquist() {
+        let mut filter = Biquad::new();
+        filter.configure(false, 1000.0, 0.707, 44100.0);
+        let mut dc = 0.0;
+        for _ in 0..2000 {
+            dc = filter.process(1.0, 0);
+        }
+        assert!((dc - 1.0).abs() < 1e-3, "{}", dc);
+
+        // Alternating samples are a tone at exactly half the sample rate
+        let mut nyquist = 0.0f32;
+        for index in 0..2000 {
+            let output = filter.process(if index % 2 == 0 { 1.0 } else { -1.0 }, 1);
+            if index >= 1000 {
+                nyquist = nyquist.max(output.abs());
+            }
+        }
+        assert!(nyquist < 1e-3, "{}", nyquist);
+    }
+
+    #[test]
+    fn delay_echoes_after_its_length_and_decays_by_feedback() {
+        // 0.25 s at 40 Hz is a 10 frame delay
+        let mut delay = Delay::new(40.0);
+        delay.configure(0.25, 0.5, 1.0, 40.0);
+        let output: Vec<f32> = (0..35)
+            .map(|frame| delay.process(if frame == 0 { [1.0, 1.0] } else { [0.0, 0.0] })[0])
+            .collect();
+
+        for (frame, &value) in output.iter().enumerate() {
+            let expected = match frame {
+                0 => 1.0,   // Dry impulse
+                10 => 1.0,  // First echo at full mix
+                20 => 0.5,  // Each repeat scaled by the feedback
+                30 => 0.25,
+                _ => 0.0,
+            };
+            assert_eq!(value, expected, "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn chain_update_keeps_state_when_only_parameters_change() {
+        let delay = |mix: f32| EffectSettings::Delay { time: 0.25, feedback: 0.5, mix };
+        let mut chain = EffectChain::new(40);
+        chain.update(&[delay(1.0)]);
+
+        // Impulse in the first six frames; the echo is due at frame 10
+        let mut first = vec![0.0f32; 12];
+        first[0] = 1.0;
+        first[1] = 1.0;
+        chain.process(&mut first);
+
+        chain.update(&[delay(0.5)]);
+        let mut second = vec![0.0f32; 12];
+        chain.process(&mut second);
+        assert_eq!(second[8], 0.5, "the delay line was reset: {:?}", second);
+        assert_eq!(second[9], 0.5);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use crate::interpreter::Value;
+
+/// One scope of variables. Lookups and assignments walk outwards through the
+/// enclosing scopes; `define` always binds in this scope.
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: None,
+        }
+    }
+
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Self {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    pub fn define(&mut self, name: String, value: Value) {
+        self.values.insert(name, value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref().and_then(|enclosing| enclosing.borrow().get(name)),
+        }
+    }
+
+    /// Update the innermost existing binding of `name`. Returns false if no scope has it.
+    pub fn assign(&mut self, name: &str, value: Value) -> bool {
+        if let Some(slot) = self.values.get_mut(name) {
+            *slot = value;
+            true
+        } else if let Some(ref enclosing) = self.enclosing {
+            enclosing.borrow_mut().assign(name, value)
+        } else {
+            false
+        }
+    }
+
+    // Only touches this scope
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        self.values.remove(name)
+    }
+
+    pub fn values(&self) -> &HashMap<String, Value> {
+        &self.values
+    }
+
+    pub fn replace_values(&mut self, values: HashMap<String, Value>) {
+        self.values = values;
+    }
+}
+
+// Closures can hold the scope they are stored in, so only print names to avoid recursing forever
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.values.keys().collect();
+        names.sort();
+        f.debug_struct("Environment")
+            .field("names", &names)
+            .field("enclosed", &self.enclosing.is_some())
+            .finish()
+    }
+}
\ No newline at end of file
@@ -199,16 +199,20 @@ impl GameObjectManager {
     }
     
     pub fn get_all_squares(&self) -> Vec<Square> {
-        self.objects.values()
+        let mut squares: Vec<Square> = self.objects.values()
             .filter_map(|obj| match obj {
                 GameObject::Square(square) => Some(square.clone()),
                 _ => None,
             })
-            .collect()
+            .collect();
+        squares.sort_by_key(|square| square.id);
+        squares
     }
     
     pub fn get_all_ball_ids(&self) -> Vec<u32> {
-        self.balls.keys().cloned().collect()
+        let mut ids: Vec<u32> = self.balls.keys().cloned().collect();
+        ids.sort();
+        ids
     }
     
     pub fn get_ball_mut(&mut self, ball_id: u32) -> Option<&mut Ball> {
@@ -219,6 +223,18 @@ impl GameObjectManager {
         }
     }
 
+    pub fn get_all_balls_mut(&mut self) -> Vec<&mut Ball> {
+        let mut balls: Vec<&mut Ball> = self.objects.values_mut()
+            .filter_map(|obj| match obj {
+                GameObject::Ball(ball) => Some(ball),
+                _ => None,
+            })
+            .collect();
+        // Keep pair ordering stable between runs
+        balls.sort_by_key(|ball| ball.id);
+        balls
+    }
+
     pub fn get_square_mut(&mut self, square_id: u32) -> Option<&mut Square> {
         if let Some(GameObject::Square(square)) = self.objects.get_mut(&square_id) {
             Some(square)
@@ -336,6 +352,13 @@ fn copy_audio_file_to_samples(source_path: &str) -> Result<String, Box<dyn std::
     let dest_path = format!("samples/{}", filename);
     let dest = Path::new(&dest_path);
     
+    // Already in the samples directory: copying a file onto itself would truncate it
+    if let (Ok(a), Ok(b)) = (fs::canonicalize(source), fs::canonicalize(dest)) {
+        if a == b {
+            return Ok(dest_path);
+        }
+    }
+    
     // Copy the file
     fs::copy(source, dest)?;
     
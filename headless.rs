@@ -0,0 +1,126 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::audio_engine;
+use crate::interpreter::Interpreter;
+
+const USAGE: &str = "Usage: cantv3 --headless <script.cant> [--seconds N] [--out collisions.jsonl] [--render out.wav]";
+
+// Options for running a scene without a window or sound card
+pub struct HeadlessOptions {
+    pub script_path: String,
+    pub seconds: f64,
+    pub output_path: Option<String>, // Defaults to stdout
+    pub render_path: Option<String>, // Also mix the run into this WAV file
+}
+
+impl HeadlessOptions {
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut script_path = None;
+        let mut seconds = 10.0;
+        let mut output_path = None;
+        let mut render_path = None;
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--headless" => {},
+                "--seconds" => {
+                    let value = iter.next().ok_or_else(|| format!("--seconds expects a value\n{}", USAGE))?;
+                    seconds = value.parse::<f64>()
+                        .map_err(|_| format!("Invalid number of seconds: {}", value))?;
+                    if !seconds.is_finite() || seconds < 0.0 || seconds > audio_engine::MAX_RENDER_SECONDS {
+                        return Err(format!("Number of seconds must be between 0 and {}", audio_engine::MAX_RENDER_SECONDS));
+                    }
+                },
+                "--out" => {
+                    let value = iter.next().ok_or_else(|| format!("--out expects a file path\n{}", USAGE))?;
+                    output_path = Some(value.clone());
+                },
+                "--render" => {
+                    let value = iter.next().ok_or_else(|| format!("--render expects a WAV file path\n{}", USAGE))?;
+                    render_path = Some(value.clone());
+                },
+                other if other.starts_with("--") => {
+                    return Err(format!("Unknown option: {}\n{}", other, USAGE));
+                },
+                other => {
+                    if script_path.is_some() {
+                        return Err(format!("Only one script can be run at a time\n{}", USAGE));
+                    }
+                    script_path = Some(other.to_string());
+                }
+            }
+        }
+
+        let script_path = script_path.ok_or_else(|| USAGE.to_string())?;
+        Ok(Self { script_path, seconds, output_path, render_path })
+    }
+}
+
+pub fn run_from_args(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = HeadlessOptions::from_args(args)?;
+    run(&options)
+}
+
+// Run a .cant script, play the scene for the requested simulated time and
+// write every collision as one JSON object per line
+pub fn run(options: &HeadlessOptions) -> Result<(), Box<dyn std::error::Error>> {
+    audio_engine::init_headless_audio();
+
+    let script = std::fs::read_to_string(&options.script_path)
+        .map_err(|e| format!("Cannot read script '{}': {}", options.script_path, e))?;
+
+    let mut interpreter = Interpreter::new();
+    interpreter.enable_collision_log();
+
+    interpreter.execute_command(&script, 0, 0)
+        .map_err(|e| format!("Error in '{}': {}", options.script_path, e))?;
+    print_script_errors(&mut interpreter);
+
+    if let Some(render_path) = &options.render_path {
+        // The render plays the scene itself, starting from the state before any play in the script
+        if interpreter.is_playing() {
+            interpreter.execute_command("stop", 0, 0)?;
+        }
+        let message = interpreter.render_to_wav(render_path, options.seconds)?;
+        eprintln!("{}", message);
+    } else {
+        if !interpreter.is_playing() {
+            interpreter.execute_command("play", 0, 0)?;
+        }
+
+        // Step exactly one fixed tick at a time so the run doesn't depend on the host
+        let tick = 1.0 / interpreter.get_tick_rate();
+        let ticks = (options.seconds / tick).round() as u64;
+        for _ in 0..ticks {
+            if !interpreter.is_playing() {
+                break;
+            }
+            interpreter.update_physics(tick);
+        }
+    }
+
+    print_script_errors(&mut interpreter);
+
+    let events = interpreter.take_collision_log();
+    let mut writer: Box<dyn Write> = match &options.output_path {
+        Some(path) => Box::new(BufWriter::new(File::create(path)
+            .map_err(|e| format!("Cannot create output file '{}': {}", path, e))?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    for event in &events {
+        writeln!(writer, "{}", serde_json::to_string(event)?)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// There is no console without a window, so script errors go to stderr
+fn print_script_errors(interpreter: &mut Interpreter) {
+    for message in interpreter.take_console_messages() {
+        eprintln!("{}", message);
+    }
+}
\ No newline at end of file
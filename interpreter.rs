@@ -1,4 +1,7 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
+use serde::Serialize;
 use thiserror::Error;
 use crate::grid::GridState;
 use crate::lexer::{Lexer, LexerError, Token, TokenType};
@@ -8,9 +11,23 @@ use crate::game_objects::{GameObjectManager, GameObject};
 use crate::physics_engine::{PhysicsEngine, CollisionInfo, CollisionType};
 use crate::game_state::GameStateManager;
 use crate::console::Console;
+use crate::environment::Environment;
 use crate::script_editor::ScriptEditor;
 use crate::ball::Ball;
 use crate::square::Square;
+use crate::transport::Transport;
+use crate::synth::{SynthSettings, Waveform};
+use crate::effects::EffectSettings;
+use crate::midi::{self, MidiRecording, VelocitySource};
+use crate::osc::{OscArg, OscMessage, OscServer};
+use crate::project::{self, ProjectFile, GridData, BallData, SquareData, SampleData};
+
+// Fixed simulation step rate (ticks per second) used unless a script calls tickrate()
+const DEFAULT_TICK_RATE: f64 = 240.0;
+// Longest frame time fed into the accumulator, so a hitch doesn't trigger a flood of catch-up ticks
+const MAX_FRAME_TIME: f64 = 0.25;
+// Most numbers a range used as a value can produce; for loops step through ranges without this limit
+const MAX_RANGE_LIST_LEN: f64 = 1_000_000.0;
 
 #[derive(Error, Debug)]
 pub enum InterpreterError {
@@ -28,6 +45,30 @@ pub enum InterpreterError {
     TypeError(String),
     #[error("Return value: {0:?}")]
     Return(Value),
+    // A runtime error tagged with where in which script it happened and the calls leading there
+    #[error("{location}: {error}{trace}")]
+    Located {
+        error: Box<InterpreterError>,
+        location: String, // e.g. "square4, line 3, column 5"
+        trace: String,    // One "\n  in f() called at ..." line per active call, innermost first
+    },
+}
+
+// A registered "on <event>" block
+#[derive(Debug, Clone)]
+struct EventHandler {
+    event: String,
+    target: Option<String>,  // hit only: name of the object that must be involved
+    owner: Option<u32>,      // Object whose script declared it; None for global handlers
+    body: Rc<Stmt>,
+    closure: Rc<RefCell<Environment>>,
+}
+
+// A user function call in progress, for error traces
+#[derive(Debug, Clone)]
+struct CallFrame {
+    function: String,
+    call_site: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -40,8 +81,10 @@ pub enum Value {
         name: String,
         parameters: Vec<String>,
         body: Box<Stmt>,
+        closure: Rc<RefCell<Environment>>, // Scope the function was defined in
     },
     GameObject(u32), // Reference to game object by ID
+    List(Rc<RefCell<Vec<Value>>>), // Shared, so push/pop through any variable holding it are seen by all
 }
 
 impl Value {
@@ -61,6 +104,10 @@ impl Value {
             Value::Nil => "nil".to_string(),
             Value::Function { name, .. } => format!("<function {}>", name),
             Value::GameObject(id) => format!("<object {}>", id),
+            Value::List(items) => {
+                let items: Vec<String> = items.borrow().iter().map(|item| item.to_string()).collect();
+                format!("[{}]", items.join(", "))
+            },
         }
     }
     
@@ -70,14 +117,39 @@ impl Value {
             _ => None,
         }
     }
+    
+    pub fn new_list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+    
+    // Copy that shares no lists with the original, for play/stop snapshots
+    pub fn deep_copy(&self) -> Value {
+        match self {
+            Value::List(items) => Value::new_list(items.borrow().iter().map(|item| item.deep_copy()).collect()),
+            other => other.clone(),
+        }
+    }
+
 
+}
 
+// One collision as written to the headless collision log
+#[derive(Debug, Clone, Serialize)]
+pub struct CollisionEvent {
+    pub time: f64,
+    pub kind: String, // "square", "wall" or "ball"
+    pub ball: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub square: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub other_ball: Option<String>,
+    pub hits: u32, // Hits of this ball on the object it struck
 }
 
 pub struct Interpreter {
     grid_state: Option<GridState>,
-    globals: HashMap<String, Value>,
-    environment: HashMap<String, Value>,
+    globals: Rc<RefCell<Environment>>, // Outermost scope: object names and top-level variables
+    environment: Rc<RefCell<Environment>>, // Innermost scope of whatever is executing
     game_objects: GameObjectManager,
     game_state_manager: GameStateManager,
     physics_engine: PhysicsEngine,
@@ -85,6 +157,8 @@ pub struct Interpreter {
     cursor_y: u32,
     script_editor: Option<ScriptEditor>,
     current_script_owner: Option<u32>,
+    colliding_ball: Option<u32>, // Ball whose hit is running the current square script
+    locate_errors: bool, // False while running a single console line, whose errors need no position
     verbose_mode: bool,
     graphics_update_needed: bool,
     // Add in-memory script storage
@@ -97,14 +171,31 @@ pub struct Interpreter {
     file_selection_mode: bool,
     available_files: Vec<String>,
     selected_file_index: usize,
+    // Fixed-timestep simulation state
+    tick_rate: f64,
+    time_accumulator: f64,
+    simulation_time: f64,
+    transport: Transport,
+    pan_mode: bool, // Pan collision sounds by the ball's x position
+    collision_pan: f32, // Pan of sounds triggered by the collision being handled
+    master_effects: Vec<EffectSettings>,
+    midi_recording: Option<MidiRecording>,
+    osc: Option<OscServer>,
+    collision_log: Option<Vec<CollisionEvent>>,
+    // Error reporting
+    current_span: Option<Span>,
+    call_stack: Vec<CallFrame>,
+    console_messages: Vec<String>, // Script errors waiting to be shown in the console
+    event_handlers: Vec<EventHandler>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
         let mut interpreter = Self {
             grid_state: None,
-            globals: HashMap::new(),
-            environment: HashMap::new(),
+            globals: Rc::clone(&globals),
+            environment: globals,
             game_objects: GameObjectManager::new(),
             game_state_manager: GameStateManager::new(),
             physics_engine: PhysicsEngine::new(10.0, 10.0, 50.0), // Default grid: 10x10 with 50px tiles
@@ -112,6 +203,8 @@ impl Interpreter {
             cursor_y: 0,
             script_editor: None,
             current_script_owner: None,
+            colliding_ball: None,
+            locate_errors: true,
             verbose_mode: false,
             graphics_update_needed: false,
             memory_scripts: HashMap::new(),
@@ -121,6 +214,20 @@ impl Interpreter {
             file_selection_mode: false,
             available_files: Vec::new(),
             selected_file_index: 0,
+            tick_rate: DEFAULT_TICK_RATE,
+            time_accumulator: 0.0,
+            simulation_time: 0.0,
+            transport: Transport::new(),
+            pan_mode: false,
+            collision_pan: 0.0,
+            master_effects: Vec::new(),
+            midi_recording: None,
+            osc: None,
+            collision_log: None,
+            current_span: None,
+            call_stack: Vec::new(),
+            console_messages: Vec::new(),
+            event_handlers: Vec::new(),
         };
         interpreter.register_builtins();
         interpreter
@@ -146,6 +253,7 @@ impl Interpreter {
     fn execute_play(&mut self) -> Result<Value, InterpreterError> {
         if self.game_state_manager.is_paused() {
             // Resume from paused state
+            self.time_accumulator = 0.0;
             self.game_state_manager.start_play();
             Ok(Value::String("Game resumed".to_string()))
         } else if !self.game_state_manager.is_playing() {
@@ -153,10 +261,15 @@ impl Interpreter {
             self.game_state_manager.save_original_state(
                 &self.game_objects,
                 &self.grid_state,
-                &self.environment
+                &self.snapshot_globals()
             );
             
+            self.time_accumulator = 0.0;
+            self.simulation_time = 0.0;
             self.game_state_manager.start_play();
+            
+            self.register_object_handlers();
+            self.fire_event("play", None, None);
             Ok(Value::String("Game started".to_string()))
         } else {
             // Already playing
@@ -171,7 +284,7 @@ impl Interpreter {
             self.game_state_manager.save_paused_state(
                 &self.game_objects,
                 &self.grid_state,
-                &self.environment
+                &self.snapshot_globals()
             );
             self.game_state_manager.pause_play();
             Ok(Value::String("Game paused".to_string()))
@@ -183,13 +296,31 @@ impl Interpreter {
     // Update the execute_stop method
     fn execute_stop(&mut self) -> Result<Value, InterpreterError> {
         // Stop the physics simulation
+        let was_running = self.game_state_manager.is_playing() || self.game_state_manager.is_paused();
+        if was_running {
+            if let Some(recording) = self.midi_recording.as_mut() {
+                recording.end_run(self.simulation_time);
+            }
+        }
         self.game_state_manager.stop_play();
+        self.time_accumulator = 0.0;
+        self.simulation_time = 0.0;
+        
+        // Handlers see the scene as it was when stopped, before it is restored
+        if was_running {
+            self.fire_event("stop", None, None);
+        }
         
         // Restore the original saved state if it exists
         if let Some(saved) = self.game_state_manager.get_saved_state() {
             self.game_objects = saved.game_objects.clone();
             self.grid_state = saved.grid_state.clone();
-            self.environment = saved.environment.clone();
+            // Restore in place so closures holding the global scope see the restored values
+            let restored = saved.environment.iter()
+                .map(|(name, value)| (name.clone(), value.deep_copy()))
+                .collect();
+            self.globals.borrow_mut().replace_values(restored);
+            self.environment = Rc::clone(&self.globals);
             Ok(Value::String("Game stopped and state restored to original".to_string()))
         } else {
             Ok(Value::String("Game stopped (no saved state to restore)".to_string()))
@@ -200,51 +331,388 @@ impl Interpreter {
         self.game_state_manager.is_playing()
     }
 
+    // Feeds wall-clock time into the fixed-step accumulator and runs as many
+    // whole ticks as have elapsed, so results don't depend on the frame rate
     pub fn update_physics(&mut self, dt: f64) {
         if self.is_playing() {
-            let squares = self.game_objects.get_all_squares();
-            let mut all_collisions = Vec::new();
+            let fixed_dt = 1.0 / self.tick_rate;
+            self.time_accumulator += dt.clamp(0.0, MAX_FRAME_TIME);
             
-            for ball_id in self.game_objects.get_all_ball_ids() {
-                if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
-                    let collisions = self.physics_engine.update_ball(ball, dt, &squares);
-                    all_collisions.extend(collisions);
-                }
+            while self.time_accumulator >= fixed_dt && self.is_playing() {
+                self.time_accumulator -= fixed_dt;
+                self.step_simulation(fixed_dt);
             }
+        }
+    }
+    
+    pub fn get_simulation_time(&self) -> f64 {
+        self.simulation_time
+    }
+    
+    pub fn get_tick_rate(&self) -> f64 {
+        self.tick_rate
+    }
+
+    fn step_simulation(&mut self, dt: f64) {
+        let squares = self.game_objects.get_all_squares();
+        let all_collisions = {
+            let mut balls = self.game_objects.get_all_balls_mut();
+            self.physics_engine.step(&mut balls, &squares, dt)
+        };
+        let step_start = self.simulation_time;
+        self.simulation_time += dt;
+        
+        // Process physics collisions
+        for collision in all_collisions {
+            let collision_time = step_start + collision.time;
+            self.collision_pan = self.pan_for_collision(collision.ball_id, collision.position.0);
+            self.route_ball_audio(collision.ball_id);
+            
+            // Sounds triggered while handling this collision start at its simulation time,
+            // or on the next grid line when quantizing
+            crate::audio_engine::set_trigger_time(collision_time, self.transport.quantize_time(collision_time));
             
-            // Process physics collisions
-            for collision in all_collisions {
-                match collision.collision_type {
-                    CollisionType::Wall => {
-                        // Record wall hit for the ball
+            // Both balls of a contact get a CollisionInfo; hit handlers and OSC treat it as
+            // one hit, seen from the lower-numbered ball
+            let second_of_pair = matches!(collision.collision_type, CollisionType::Ball)
+                && collision.other_object_id.map_or(false, |other| other < collision.ball_id);
+            
+            match collision.collision_type {
+                CollisionType::Wall => {
+                    // Record wall hit for the ball
+                    if let Some(ball) = self.game_objects.get_ball_mut(collision.ball_id) {
+                        ball.record_hit(0); // Use 0 or special ID for walls
+                    }
+                    
+                    if self.verbose_mode {
+                        println!("{}: wall collision", 
+                            self.game_objects.get_ball_name(collision.ball_id).unwrap_or("unknown".to_string()));
+                    }
+                    
+                    self.log_collision_event(collision_time, collision.ball_id, None);
+                    self.fire_event("wall", Some(collision.ball_id), None);
+                },
+                CollisionType::Square => {
+                    if let Some(square_id) = collision.other_object_id {
+                        // Record hits for both objects
                         if let Some(ball) = self.game_objects.get_ball_mut(collision.ball_id) {
-                            ball.record_hit(0); // Use 0 or special ID for walls
+                            ball.record_hit(square_id);
+                        }
+                        if let Some(square) = self.game_objects.get_square_mut(square_id) {
+                            square.record_hit(collision.ball_id);
                         }
                         
                         if self.verbose_mode {
-                            println!("{}: wall collision", 
-                                self.game_objects.get_ball_name(collision.ball_id).unwrap_or("unknown".to_string()));
+                            self.print_collision_info(collision.ball_id, square_id);
                         }
-                    },
-                    CollisionType::Square => {
-                        if let Some(square_id) = collision.other_object_id {
-                            // Record hits for both objects
-                            if let Some(ball) = self.game_objects.get_ball_mut(collision.ball_id) {
-                                ball.record_hit(square_id);
-                            }
-                            if let Some(square) = self.game_objects.get_square_mut(square_id) {
-                                square.record_hit(collision.ball_id);
-                            }
-                            
-                            if self.verbose_mode {
-                                self.print_collision_info(collision.ball_id, square_id);
+                        
+                        self.log_collision_event(collision_time, collision.ball_id, Some(square_id));
+                        self.execute_collision_script(collision.ball_id, square_id);
+                        self.fire_event("hit", Some(collision.ball_id), Some(square_id));
+                    }
+                },
+                CollisionType::Ball => {
+                    if let Some(other_ball_id) = collision.other_object_id {
+                        // Each ball gets its own CollisionInfo, so only record this side
+                        if let Some(ball) = self.game_objects.get_ball_mut(collision.ball_id) {
+                            ball.record_hit(other_ball_id);
+                        }
+                        
+                        if self.verbose_mode {
+                            if let Some(GameObject::Ball(ball)) = self.game_objects.get_object(collision.ball_id) {
+                                println!("{}: {} hits", ball.get_friendly_name(), ball.get_hit_count(other_ball_id));
                             }
-                            
-                            self.execute_collision_script(collision.ball_id, square_id);
+                        }
+                        
+                        self.log_collision_event(collision_time, collision.ball_id, Some(other_ball_id));
+                        self.execute_ball_collision_script(collision.ball_id, other_ball_id);
+                        if !second_of_pair {
+                            self.fire_event("hit", Some(collision.ball_id), Some(other_ball_id));
                         }
                     }
                 }
             }
+            
+            // After scripts and handlers, so they can set this hit's pitch, rate, volume or effects.
+            // In a ball-ball contact both balls sound, so each also records its own MIDI note
+            self.route_ball_audio(collision.ball_id);
+            self.play_ball_audio(collision.ball_id);
+            self.record_midi_note(self.transport.quantize_time(collision_time), collision.ball_id, collision.other_object_id);
+            if !second_of_pair {
+                self.send_osc_collision(collision.ball_id, collision.other_object_id);
+            }
+        }
+        
+        self.collision_pan = 0.0;
+        crate::audio_engine::set_trigger_bus(None, &[]);
+        
+        // Tick handlers run once per fixed step, after its collisions
+        crate::audio_engine::set_trigger_time(self.simulation_time, self.transport.quantize_time(self.simulation_time));
+        self.fire_event("tick", None, None);
+        
+        crate::audio_engine::clear_trigger_time();
+    }
+    
+    // Add or replace a handler. Scripts run on every collision, so the same
+    // owner declaring the same event again replaces its earlier handler.
+    fn register_handler(&mut self, event: &str, target: &Option<String>, body: &Stmt) {
+        let owner = self.current_script_owner;
+        self.event_handlers.retain(|handler| {
+            !(handler.event == event && handler.target == *target && handler.owner == owner)
+        });
+        self.event_handlers.push(EventHandler {
+            event: event.to_string(),
+            target: target.clone(),
+            owner,
+            body: Rc::new(body.clone()),
+            closure: Rc::clone(&self.environment),
+        });
+    }
+    
+    // Pick up "on" blocks from every ball and square script, without running the rest of the script
+    fn register_object_handlers(&mut self) {
+        self.event_handlers.retain(|handler| handler.owner.is_none());
+        
+        let mut scripts: Vec<(u32, String)> = Vec::new();
+        for ball in self.game_objects.get_all_balls_mut() {
+            if let Some(script) = ball.get_script() {
+                scripts.push((ball.id, script.to_string()));
+            }
+        }
+        for square in self.game_objects.get_all_squares() {
+            if let Some(script) = square.get_script() {
+                scripts.push((square.id, script.to_string()));
+            }
+        }
+        
+        let previous_owner = self.current_script_owner;
+        let globals = Rc::clone(&self.globals);
+        for (owner_id, script) in scripts {
+            let program = Lexer::new(&script).tokenize()
+                .map_err(InterpreterError::from)
+                .and_then(|tokens| Parser::new(tokens).parse().map_err(InterpreterError::from));
+            self.current_script_owner = Some(owner_id);
+            match program {
+                Ok(program) => {
+                    let previous_env = std::mem::replace(&mut self.environment, Rc::clone(&globals));
+                    for statement in &program.statements {
+                        if let Stmt::On { event, target, body } = statement.inner() {
+                            self.register_handler(event, target, body);
+                        }
+                    }
+                    self.environment = previous_env;
+                },
+                Err(e) => self.report_script_error(&e),
+            }
+        }
+        self.current_script_owner = previous_owner;
+    }
+    
+    /// Run the handlers for `event`. `ball` is the ball involved (wall, hit) and
+    /// `other` what it hit; handlers get them as the variables `ball` and `other`.
+    fn fire_event(&mut self, event: &str, ball: Option<u32>, other: Option<u32>) {
+        if !self.event_handlers.iter().any(|handler| handler.event == event) {
+            return;
+        }
+        
+        let names: Vec<String> = [ball, other].iter().flatten()
+            .filter_map(|&id| self.game_objects.get_ball_name(id).or_else(|| self.game_objects.get_square_name(id)))
+            .collect();
+        
+        let handlers: Vec<EventHandler> = self.event_handlers.iter()
+            .filter(|handler| handler.event == event)
+            .filter(|handler| match handler.owner {
+                None => true,
+                // Object handlers fire for their own collisions; tick, play and stop always
+                Some(owner) => ball.is_none() || ball == Some(owner) || other == Some(owner),
+            })
+            .filter(|handler| match handler.target {
+                Some(ref target) => names.contains(target),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        
+        for handler in handlers {
+            // Skip handlers of objects destroyed since they were registered
+            if let Some(owner) = handler.owner {
+                if self.game_objects.get_object(owner).is_none() {
+                    continue;
+                }
+            }
+            
+            let mut scope = Environment::with_enclosing(Rc::clone(&handler.closure));
+            if let Some(ball) = ball {
+                scope.define("ball".to_string(), Value::GameObject(ball));
+            }
+            if let Some(other) = other {
+                scope.define("other".to_string(), Value::GameObject(other));
+            }
+            
+            let previous_owner = std::mem::replace(&mut self.current_script_owner, handler.owner);
+            let previous_locate = std::mem::replace(&mut self.locate_errors, true);
+            let statements = match handler.body.as_ref() {
+                Stmt::Block(statements) => statements.as_slice(),
+                other => std::slice::from_ref(other),
+            };
+            if let Err(e) = self.execute_block(statements, Rc::new(RefCell::new(scope))) {
+                self.report_script_error(&e);
+            }
+            self.locate_errors = previous_locate;
+            self.current_script_owner = previous_owner;
+        }
+    }
+
+    fn play_ball_audio(&self, ball_id: u32) {
+        if let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) {
+            ball.play_collision_audio(self.collision_pan);
+        }
+    }
+    
+    // Sounds from this ball's collision, its slices included, go through the ball's effects
+    fn route_ball_audio(&self, ball_id: u32) {
+        if let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) {
+            crate::audio_engine::set_trigger_bus(Some(ball_id), &ball.effects);
+        }
+    }
+    
+    // One note per collision while midi_record is on: channel from the ball, note
+    // from the square hit or else the ball
+    fn record_midi_note(&mut self, time: f64, ball_id: u32, other_id: Option<u32>) {
+        let Some(recording) = self.midi_recording.as_mut() else {
+            return;
+        };
+        let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) else {
+            return;
+        };
+        let square_note = match other_id.and_then(|id| self.game_objects.get_object(id)) {
+            Some(GameObject::Square(square)) => square.midi_note,
+            _ => None,
+        };
+        let note = square_note.or(ball.midi_note).unwrap_or(midi::DEFAULT_NOTE);
+        let channel = midi::channel_for_ball(ball.sequence_number);
+        let velocity = recording.velocity_source.velocity(ball.speed, ball.audio_volume);
+        recording.add_note(time, ball_id, &ball.get_friendly_name(), channel, note, velocity);
+    }
+    
+    // /cant/collision <ball> <square, ball or "wall"> <hits between them>
+    fn send_osc_collision(&self, ball_id: u32, other_id: Option<u32>) {
+        let Some(server) = self.osc.as_ref() else {
+            return;
+        };
+        let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) else {
+            return;
+        };
+        let other = match other_id.and_then(|id| self.game_objects.get_object(id)) {
+            Some(GameObject::Square(square)) => square.get_friendly_name(),
+            Some(GameObject::Ball(other)) => other.get_friendly_name(),
+            None => "wall".to_string(),
+        };
+        let hits = ball.get_hit_count(other_id.unwrap_or(0));
+        server.send(&OscMessage::new("/cant/collision", vec![
+            OscArg::String(ball.get_friendly_name()),
+            OscArg::String(other),
+            OscArg::Int(hits as i32),
+        ]));
+    }
+    
+    /// Runs OSC messages that arrived since the last call, like commands typed
+    /// into the console. Returns their output; errors go to the console messages.
+    pub fn poll_osc(&mut self) -> Vec<String> {
+        let received = match self.osc.as_mut() {
+            Some(server) => server.poll(),
+            None => return Vec::new(),
+        };
+        
+        let mut output = Vec::new();
+        for message in received {
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    self.console_messages.push(format!("OSC: {}", e));
+                    continue;
+                },
+            };
+            match self.execute_osc_message(&message) {
+                Ok(result) if !result.is_empty() => output.push(format!("OSC {}: {}", message.address, result)),
+                Ok(_) => {},
+                Err(e) => self.console_messages.push(format!("OSC {}: {}", message.address, e)),
+            }
+        }
+        output
+    }
+    
+    fn execute_osc_message(&mut self, message: &OscMessage) -> Result<String, InterpreterError> {
+        let command = match message.address.as_str() {
+            // Arguments are joined, so /cant/cmd "set speed ball1" 2 also works
+            "/cant/cmd" => {
+                if message.args.is_empty() {
+                    return Err(InterpreterError::RuntimeError("/cant/cmd expects a command string".to_string()));
+                }
+                message.args.iter().map(OscArg::to_text).collect::<Vec<_>>().join(" ")
+            },
+            "/cant/play" => "play".to_string(),
+            "/cant/pause" => "pause".to_string(),
+            "/cant/stop" => "stop".to_string(),
+            address => return Err(InterpreterError::RuntimeError(format!(
+                "Unknown OSC address '{}': use /cant/cmd, /cant/play, /cant/pause or /cant/stop", address
+            ))),
+        };
+        let (cursor_x, cursor_y) = (self.cursor_x, self.cursor_y);
+        self.execute_command(&command, cursor_x, cursor_y)
+    }
+    
+    // A ball's own pan wins; otherwise in pan mode the collision's x sets it (left wall = -1, right wall = 1)
+    fn pan_for_collision(&self, ball_id: u32, x: f64) -> f32 {
+        let fixed_pan = match self.game_objects.get_object(ball_id) {
+            Some(GameObject::Ball(ball)) => ball.pan,
+            _ => None,
+        };
+        match fixed_pan {
+            Some(pan) => pan as f32,
+            None if self.pan_mode && self.physics_engine.grid_width > 0.0 => {
+                ((x / self.physics_engine.grid_width) * 2.0 - 1.0).clamp(-1.0, 1.0) as f32
+            },
+            None => 0.0,
+        }
+    }
+    
+    // Start recording every collision into the collision log (used by the headless runner)
+    pub fn enable_collision_log(&mut self) {
+        if self.collision_log.is_none() {
+            self.collision_log = Some(Vec::new());
+        }
+    }
+    
+    pub fn take_collision_log(&mut self) -> Vec<CollisionEvent> {
+        self.collision_log.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+    
+    fn log_collision_event(&mut self, time: f64, ball_id: u32, other_id: Option<u32>) {
+        if self.collision_log.is_none() {
+            return;
+        }
+        
+        let (ball_name, hits) = match self.game_objects.get_object(ball_id) {
+            Some(GameObject::Ball(ball)) => (ball.get_friendly_name(), ball.get_hit_count(other_id.unwrap_or(0))),
+            _ => return,
+        };
+        
+        let (kind, square, other_ball) = match other_id.and_then(|id| self.game_objects.get_object(id)) {
+            Some(GameObject::Square(square)) => ("square", Some(square.get_friendly_name()), None),
+            Some(GameObject::Ball(other)) => ("ball", None, Some(other.get_friendly_name())),
+            None => ("wall", None, None),
+        };
+        
+        if let Some(log) = self.collision_log.as_mut() {
+            log.push(CollisionEvent {
+                time,
+                kind: kind.to_string(),
+                ball: ball_name,
+                square,
+                other_ball,
+                hits,
+            });
         }
     }
 
@@ -269,17 +737,23 @@ impl Interpreter {
         let mut parser = Parser::new(tokens);
         let program = parser.parse()?;
 
-        // Execute
-        let mut result = Value::Nil;
-        for statement in program.statements {
-            result = self.execute_statement(&statement)?;
-        }
+        // Execute; a single console line has no other lines for a position to tell apart
+        let previous_locate = std::mem::replace(&mut self.locate_errors, input.trim().lines().count() > 1);
+        let result = program.statements.iter()
+            .try_fold(Value::Nil, |_, statement| self.execute_statement(statement));
+        self.locate_errors = previous_locate;
 
-        Ok(result.to_string())
+        Ok(result?.to_string())
     }
 
     fn execute_statement(&mut self, stmt: &Stmt) -> Result<Value, InterpreterError> {
         match stmt {
+            Stmt::Spanned { span, stmt } => {
+                let previous = self.current_span.replace(*span);
+                let result = self.execute_statement(stmt);
+                self.current_span = previous;
+                result.map_err(|e| self.locate_error(e, *span))
+            },
             Stmt::Expression(expr) => self.evaluate_expression(expr),
             Stmt::Let { name, initializer } => {
                 let value = if let Some(init) = initializer {
@@ -287,15 +761,12 @@ impl Interpreter {
                 } else {
                     Value::Nil
                 };
-                self.environment.insert(name.clone(), value.clone());
+                self.environment.borrow_mut().define(name.clone(), value.clone());
                 Ok(value)
             },
             Stmt::Block(statements) => {
-                let mut result = Value::Nil;
-                for statement in statements {
-                    result = self.execute_statement(statement)?;
-                }
-                Ok(result)
+                let scope = Environment::with_enclosing(Rc::clone(&self.environment));
+                self.execute_block(statements, Rc::new(RefCell::new(scope)))
             },
             Stmt::If { condition, then_branch, else_branch } => {
                 let condition_value = self.evaluate_expression(condition)?;
@@ -304,7 +775,7 @@ impl Interpreter {
                 if let Expr::Binary { left: _, operator: BinaryOp::Hits, right: _ } = condition {
                     // Look ahead to see if the first statement in then_branch is a number (threshold)
                     if let Stmt::Block(statements) = then_branch.as_ref() {
-                        if let Some(Stmt::Expression(Expr::Number(threshold))) = statements.first() {
+                        if let Some(Stmt::Expression(Expr::Number(threshold))) = statements.first().map(Stmt::inner) {
                             // Compare hit count with threshold
                             if let Value::Number(hit_count) = condition_value {
                                 if hit_count >= *threshold {
@@ -337,13 +808,15 @@ impl Interpreter {
                 }
                 Ok(result)
             },
+            Stmt::For { variable, iterable, body } => self.execute_for(variable, iterable, body),
             Stmt::Function { name, parameters, body } => {
                 let function = Value::Function {
                     name: name.clone(),
                     parameters: parameters.clone(),
                     body: body.clone(),
+                    closure: Rc::clone(&self.environment),
                 };
-                self.environment.insert(name.clone(), function.clone());
+                self.environment.borrow_mut().define(name.clone(), function.clone());
                 Ok(function)
             },
             Stmt::Return(expr) => {
@@ -363,6 +836,9 @@ impl Interpreter {
             Stmt::SetSpeed { object_name, speed } => {
                 self.execute_set_speed(object_name, speed)
             },
+            Stmt::SetProperty { object_name, property, value } => {
+                self.execute_set_property(object_name, property, value)
+            },
             Stmt::Label { object_name, arguments, text } => {
                 self.execute_label(object_name, arguments, text)
             },
@@ -379,11 +855,32 @@ impl Interpreter {
                 self.execute_destroy(object_type, arguments)
             },
             Stmt::Run { script_name } => self.execute_run_command(script_name),
-            Stmt::Slice { sequence } => self.execute_slice_command(sequence),
+            Stmt::Slice { sequence, pitches } => {
+                let sequence = self.evaluate_number_sequence(sequence)?;
+                let pitches = self.evaluate_number_sequence(pitches)?;
+                self.execute_slice_command(&sequence, &pitches)
+            },
+            Stmt::SliceInto { divisions, target } => self.execute_slice_into(divisions, target.as_ref()),
             Stmt::Waveform { target } => self.execute_waveform_command(target),
+            Stmt::Fx { target, effect, arguments, remove } => {
+                self.execute_fx(target, effect, arguments, *remove)
+            },
+            Stmt::MidiRecord { start, velocity, path } => {
+                self.execute_midi_record(*start, velocity.as_deref(), path.as_ref())
+            },
+            Stmt::On { event, target, body } => {
+                self.register_handler(event, target, body);
+                let owner = self.script_owner_name().map(|name| format!(" for {}", name)).unwrap_or_default();
+                Ok(Value::String(format!("Registered 'on {}' handler{}", event, owner)))
+            },
         }
     }
 
+    fn destroy_object(&mut self, id: u32) {
+        self.game_objects.destroy_object(id);
+        crate::audio_engine::remove_effect_bus(Some(id));
+    }
+
     fn execute_destroy(&mut self, object_type: &str, arguments: &[Expr]) -> Result<Value, InterpreterError> {
         if arguments.len() != 1 {
             return Err(InterpreterError::RuntimeError("destroy expects 1 argument".to_string()));
@@ -410,7 +907,7 @@ impl Interpreter {
                     for obj_name in &objects_at_cursor {
                         if obj_name.starts_with(object_type) {
                             if let Some(obj_id) = self.game_objects.find_object_by_name(obj_name) {
-                                self.game_objects.destroy_object(obj_id);
+                                self.destroy_object(obj_id);
                                 return Ok(Value::String(format!("Destroyed {} at cursor position", obj_name)));
                             }
                         }
@@ -430,7 +927,7 @@ impl Interpreter {
                     for obj_name in &objects_at_pos {
                         if obj_name.starts_with(object_type) {
                             if let Some(obj_id) = self.game_objects.find_object_by_name(obj_name) {
-                                self.game_objects.destroy_object(obj_id);
+                                self.destroy_object(obj_id);
                                 return Ok(Value::String(format!("Destroyed {} at ({}, {})", obj_name, x, y)));
                             }
                         }
@@ -465,10 +962,13 @@ impl Interpreter {
                     return Ok(Value::String(format!("cursor:{}:{}", self.cursor_x, self.cursor_y)));
                 }
                 
-                if let Some(value) = self.environment.get(name) {
-                    Ok(value.clone())
-                } else if let Some(value) = self.globals.get(name) {
-                    Ok(value.clone())
+                if let Some(value) = self.lookup(name) {
+                    Ok(value)
+                } else if name == "beat" {
+                    // Transport position, unless a script defined its own variable with the name
+                    Ok(Value::Number(self.transport.beat_at(self.simulation_time) as f64))
+                } else if name == "bar" {
+                    Ok(Value::Number(self.transport.bar_at(self.simulation_time) as f64))
                 } else {
                     Err(InterpreterError::UndefinedVariable(name.clone()))
                 }
@@ -482,9 +982,86 @@ impl Interpreter {
                 let operand_val = self.evaluate_expression(operand)?;
                 self.apply_unary_operator(operator, operand_val)
             },
-            Expr::Call { callee, arguments } => {
+            Expr::Logical { left, operator, right } => {
+                let left_val = self.evaluate_expression(left)?;
+                // Short-circuit: the right side only runs when it can change the result
+                let result = match operator {
+                    LogicalOp::And => left_val.is_truthy() && self.evaluate_expression(right)?.is_truthy(),
+                    LogicalOp::Or => left_val.is_truthy() || self.evaluate_expression(right)?.is_truthy(),
+                };
+                Ok(Value::Boolean(result))
+            },
+            Expr::Range { start, end } => {
+                let (start, end) = self.evaluate_range_bounds(start, end)?;
+                if (end - start).abs() >= MAX_RANGE_LIST_LEN {
+                    return Err(InterpreterError::RuntimeError(format!(
+                        "Range {}..{} is too long for a list (at most {} numbers); use it in a for loop instead",
+                        start, end, MAX_RANGE_LIST_LEN
+                    )));
+                }
+                Ok(Value::new_list(range_values(start, end).map(Value::Number).collect()))
+            },
+            Expr::List(elements) => {
+                let mut items = Vec::with_capacity(elements.len());
+                for element in elements {
+                    items.push(self.evaluate_expression(element)?);
+                }
+                Ok(Value::new_list(items))
+            },
+            Expr::Index { object, index } => {
+                let target = self.evaluate_expression(object)?;
+                let index = self.evaluate_expression(index)?;
+                match target {
+                    Value::List(items) => {
+                        let items = items.borrow();
+                        let position = list_index(&index, items.len())?;
+                        Ok(items[position].clone())
+                    },
+                    Value::String(text) => {
+                        let chars: Vec<char> = text.chars().collect();
+                        let position = list_index(&index, chars.len())?;
+                        Ok(Value::String(chars[position].to_string()))
+                    },
+                    other => Err(InterpreterError::TypeError(format!("Cannot index into {}", other.to_string()))),
+                }
+            },
+            Expr::Get { object, property } => {
+                match self.evaluate_expression(object)? {
+                    Value::GameObject(id) => self.get_object_property(id, property),
+                    other => Err(InterpreterError::TypeError(format!("Cannot read .{} of {}", property, other.to_string()))),
+                }
+            },
+            Expr::Set { object, property, value } => {
+                let target = self.evaluate_expression(object)?;
+                let value = self.evaluate_expression(value)?;
+                match target {
+                    Value::GameObject(id) => {
+                        self.set_object_property(id, property, value.clone())?;
+                        Ok(value)
+                    },
+                    other => Err(InterpreterError::TypeError(format!("Cannot set .{} of {}", property, other.to_string()))),
+                }
+            },
+            Expr::IndexAssign { object, index, value } => {
+                let target = self.evaluate_expression(object)?;
+                let index = self.evaluate_expression(index)?;
+                let value = self.evaluate_expression(value)?;
+                match target {
+                    Value::List(items) => {
+                        let mut items = items.borrow_mut();
+                        let position = list_index(&index, items.len())?;
+                        items[position] = value.clone();
+                        Ok(value)
+                    },
+                    other => Err(InterpreterError::TypeError(format!("Cannot assign into {}", other.to_string()))),
+                }
+            },
+            Expr::Call { callee, arguments, span } => {
                 if let Expr::Identifier(function_name) = callee.as_ref() {
-                    self.call_function(function_name, arguments)
+                    let previous = self.current_span.replace(*span);
+                    let result = self.call_function(function_name, arguments);
+                    self.current_span = previous;
+                    result
                 } else {
                     Err(InterpreterError::RuntimeError("Only function names can be called".to_string()))
                 }
@@ -536,7 +1113,7 @@ impl Interpreter {
                         
                         // Get the ball's friendly name and store it in the environment
                         if let Some(ball_name) = self.game_objects.get_ball_name(id) {
-                            self.environment.insert(ball_name, Value::GameObject(id));
+                            self.define_global(ball_name, Value::GameObject(id));
                         }
                         
                         return Ok(Value::GameObject(id));
@@ -580,7 +1157,7 @@ impl Interpreter {
                             // Get the square's friendly name and store it in the environment
                             if let Some(GameObject::Square(square)) = self.game_objects.get_object(id) {
                                 let square_name = square.get_friendly_name();
-                                self.environment.insert(square_name, Value::GameObject(id));
+                                self.define_global(square_name, Value::GameObject(id));
                             }
                             
                             Ok(Value::GameObject(id))
@@ -593,7 +1170,11 @@ impl Interpreter {
             },
             Expr::Assignment { name, value } => {
                 let val = self.evaluate_expression(value)?;
-                self.environment.insert(name.clone(), val.clone());
+                // Update the nearest existing variable, otherwise create it in the current scope
+                let assigned = self.environment.borrow_mut().assign(name, val.clone());
+                if !assigned {
+                    self.environment.borrow_mut().define(name.clone(), val.clone());
+                }
                 Ok(val)
             },
         }
@@ -613,6 +1194,14 @@ impl Interpreter {
                             Ok(Value::Number(l / r))
                         }
                     },
+                    BinaryOp::Modulo => {
+                        if r == 0.0 {
+                            Err(InterpreterError::RuntimeError("Modulo by zero".to_string()))
+                        } else {
+                            // Never negative for a positive divisor, so step counters wrap cleanly
+                            Ok(Value::Number(l.rem_euclid(r)))
+                        }
+                    },
                     BinaryOp::Equal => Ok(Value::Boolean(l == r)),
                     BinaryOp::NotEqual => Ok(Value::Boolean(l != r)),
                     BinaryOp::Less => Ok(Value::Boolean(l < r)),
@@ -630,13 +1219,23 @@ impl Interpreter {
                     _ => Err(InterpreterError::TypeError("Invalid operation for strings".to_string())),
                 }
             },
+            (Value::List(l), Value::List(r)) => {
+                match op {
+                    BinaryOp::Add => {
+                        let mut items = l.borrow().clone();
+                        items.extend(r.borrow().iter().cloned());
+                        Ok(Value::new_list(items))
+                    },
+                    _ => Err(InterpreterError::TypeError("Invalid operation for lists".to_string())),
+                }
+            },
             (Value::GameObject(obj1_id), Value::GameObject(obj2_id)) => {
             match op {
                 BinaryOp::Hits => {
                     // Return the actual hit count between two game objects
                     let key = format!("hits({},{})", obj1_id, obj2_id);
-                    if let Some(Value::Number(count)) = self.environment.get(&key) {
-                        Ok(Value::Number(*count))
+                    if let Some(Value::Number(count)) = self.lookup(&key) {
+                        Ok(Value::Number(count))
                     } else {
                         Ok(Value::Number(0.0))
                     }
@@ -672,17 +1271,137 @@ impl Interpreter {
     }
     
     pub fn get_environment_value(&self, key: &str) -> Option<String> {
-        self.environment.get(key).map(|v| v.to_string())
+        self.lookup(key).map(|v| v.to_string())
     }
     
     // Add this new method
     pub fn remove_environment_value(&mut self, key: &str) -> Option<Value> {
-        self.environment.remove(key)
+        self.globals.borrow_mut().remove(key)
     }
     
     // Add this method for debugging
-    pub fn get_all_environment_values(&self) -> &HashMap<String, Value> {
-        &self.environment
+    pub fn get_all_environment_values(&self) -> HashMap<String, Value> {
+        self.globals.borrow().values().clone()
+    }
+    
+    // Attach the statement position, script owner and call chain to an error, once
+    fn locate_error(&self, error: InterpreterError, span: Span) -> InterpreterError {
+        match error {
+            // Control flow, already located, or carrying their own position
+            InterpreterError::Return(_)
+            | InterpreterError::Located { .. }
+            | InterpreterError::LexerError(_)
+            | InterpreterError::ParseError(_) => error,
+            // A console one-liner: plain errors, unless it failed inside a script or function
+            error if !self.locate_errors && self.current_script_owner.is_none() && self.call_stack.is_empty() => error,
+            error => {
+                let location = match self.script_owner_name() {
+                    Some(owner) => format!("{}, {}", owner, span),
+                    None => span.to_string(),
+                };
+                let trace = self.call_stack.iter().rev()
+                    .map(|frame| format!("\n  in {}() called at {}", frame.function, frame.call_site))
+                    .collect();
+                InterpreterError::Located { error: Box::new(error), location, trace }
+            }
+        }
+    }
+    
+    fn script_owner_name(&self) -> Option<String> {
+        let owner_id = self.current_script_owner?;
+        self.game_objects.get_square_name(owner_id)
+            .or_else(|| self.game_objects.get_ball_name(owner_id))
+    }
+    
+    // Queue a script failure for the console; scripts run from physics have no caller to return it to
+    fn report_script_error(&mut self, error: &InterpreterError) {
+        let message = match (&error, self.script_owner_name()) {
+            (InterpreterError::Located { .. }, _) | (_, None) => error.to_string(),
+            (_, Some(owner)) => format!("{}: {}", owner, error),
+        };
+        log::warn!("Script error: {}", message);
+        self.console_messages.push(message);
+    }
+    
+    /// Script errors raised since the last call, for display in the console.
+    pub fn take_console_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.console_messages)
+    }
+    
+    // Globals with lists copied, so changes during play don't leak into the saved state
+    fn snapshot_globals(&self) -> HashMap<String, Value> {
+        self.globals.borrow().values().iter()
+            .map(|(name, value)| (name.clone(), value.deep_copy()))
+            .collect()
+    }
+    
+    fn lookup(&self, name: &str) -> Option<Value> {
+        self.environment.borrow().get(name)
+    }
+    
+    fn define_global(&mut self, name: String, value: Value) {
+        self.globals.borrow_mut().define(name, value);
+    }
+    
+    // Runs the body once per item of the list or range, each time in a fresh scope holding the loop variable
+    fn execute_for(&mut self, variable: &str, iterable: &Expr, body: &Stmt) -> Result<Value, InterpreterError> {
+        let statements = match body {
+            Stmt::Block(statements) => statements.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        
+        let mut result = Value::Nil;
+        
+        // Ranges are stepped through without building the list, so 0..1e9 doesn't allocate it all up front
+        if let Expr::Range { start, end } = iterable {
+            let (start, end) = self.evaluate_range_bounds(start, end)?;
+            for value in range_values(start, end) {
+                result = self.execute_for_body(variable, Value::Number(value), statements)?;
+            }
+            return Ok(result);
+        }
+        
+        // Iterate over a copy, so pushing to the list inside the loop can't make it run forever
+        let values = match self.evaluate_expression(iterable)? {
+            Value::List(items) => items.borrow().clone(),
+            other => return Err(InterpreterError::TypeError(format!("for loops need a list or a range like 1..8, got {}", other.to_string()))),
+        };
+        for value in values {
+            result = self.execute_for_body(variable, value, statements)?;
+        }
+        Ok(result)
+    }
+    
+    fn execute_for_body(&mut self, variable: &str, value: Value, statements: &[Stmt]) -> Result<Value, InterpreterError> {
+        let mut scope = Environment::with_enclosing(Rc::clone(&self.environment));
+        scope.define(variable.to_string(), value);
+        self.execute_block(statements, Rc::new(RefCell::new(scope)))
+    }
+    
+    fn evaluate_range_bounds(&mut self, start: &Expr, end: &Expr) -> Result<(f64, f64), InterpreterError> {
+        let start = self.evaluate_expression(start)?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("Range start must be a number".to_string()))?;
+        let end = self.evaluate_expression(end)?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("Range end must be a number".to_string()))?;
+        if !start.is_finite() || !end.is_finite() {
+            return Err(InterpreterError::RuntimeError(format!("Range bounds must be finite numbers, got {}..{}", start, end)));
+        }
+        Ok((start, end))
+    }
+    
+    /// Runs `statements` with `scope` as the current environment, restoring the
+    /// previous one afterwards even if a statement fails or returns.
+    fn execute_block(&mut self, statements: &[Stmt], scope: Rc<RefCell<Environment>>) -> Result<Value, InterpreterError> {
+        let previous = std::mem::replace(&mut self.environment, scope);
+        let mut result = Ok(Value::Nil);
+        for statement in statements {
+            result = self.execute_statement(statement);
+            if result.is_err() {
+                break;
+            }
+        }
+        self.environment = previous;
+        result
     }
     
     fn call_function(&mut self, name: &str, arguments: &[Expr]) -> Result<Value, InterpreterError> {
@@ -692,6 +1411,43 @@ impl Interpreter {
             "tilesize" => return self.call_tilesize_function(arguments),
             "font_size" => return self.call_font_size_function(arguments),
             "sample" => return self.call_sample_function(arguments),
+            "synth" => return self.call_synth_function(arguments),
+            "tickrate" => return self.call_tickrate_function(arguments),
+            "bpm" => return self.call_bpm_function(arguments),
+            "quantize" => return self.call_quantize_function(arguments),
+            "pan" => return self.call_pan_function(arguments),
+            "osc" => return self.call_osc_function(arguments),
+            "export_slices" => return self.call_export_slices_function(arguments),
+            "len" | "push" | "pop" => return self.call_list_function(name, arguments),
+            "render" => return self.call_render_function(arguments),
+            "save" | "load" => {
+                if arguments.len() != 1 {
+                    return Err(InterpreterError::RuntimeError(format!("{} expects 1 argument: {}(\"name\")", name, name)));
+                }
+                let project_name = match self.evaluate_expression(&arguments[0])? {
+                    Value::String(project_name) => project_name,
+                    _ => return Err(InterpreterError::TypeError(format!("{} expects a project name", name))),
+                };
+                let result = if name == "save" {
+                    self.save_project(&project_name)
+                } else {
+                    self.load_project(&project_name)
+                };
+                return result.map(Value::String);
+            },
+            "polyphony" => {
+                if arguments.len() != 1 {
+                    return Err(InterpreterError::RuntimeError("polyphony expects 1 argument: polyphony(voices)".to_string()));
+                }
+                let voices = self.evaluate_expression(&arguments[0])?.as_number()
+                    .ok_or_else(|| InterpreterError::TypeError("polyphony expects a number of voices".to_string()))?;
+                if voices < 1.0 {
+                    return Err(InterpreterError::RuntimeError("polyphony must be at least 1".to_string()));
+                }
+                crate::audio_engine::set_polyphony(voices as usize)
+                    .map_err(|e| InterpreterError::RuntimeError(format!("Failed to set polyphony: {}", e)))?;
+                return Ok(Value::String(format!("Polyphony set to {} voices", voices as usize)));
+            },
             "hits" => {
                 if arguments.len() == 1 {
                     // Original single-parameter hits() - returns total hits for an object
@@ -699,7 +1455,8 @@ impl Interpreter {
                         Expr::Identifier(name) => name.clone(),
                         Expr::Self_ => {
                             if let Some(owner_id) = self.current_script_owner {
-                                if let Some(name) = self.game_objects.get_square_name(owner_id) {
+                                if let Some(name) = self.game_objects.get_square_name(owner_id)
+                                    .or_else(|| self.game_objects.get_ball_name(owner_id)) {
                                     name
                                 } else {
                                     return Err(InterpreterError::RuntimeError("Script owner not found".to_string()));
@@ -745,7 +1502,8 @@ impl Interpreter {
                             Expr::Identifier(name) => Ok(name.clone()),
                             Expr::Self_ => {
                                 if let Some(owner_id) = self.current_script_owner {
-                                    if let Some(name) = self.game_objects.get_square_name(owner_id) {
+                                    if let Some(name) = self.game_objects.get_square_name(owner_id)
+                                        .or_else(|| self.game_objects.get_ball_name(owner_id)) {
                                         Ok(name)
                                     } else {
                                         Err(InterpreterError::RuntimeError("Script owner not found".to_string()))
@@ -860,7 +1618,7 @@ impl Interpreter {
                 
                 // Get the ball's friendly name and store it in the environment
                 if let Some(ball_name) = self.game_objects.get_ball_name(id) {
-                    self.environment.insert(ball_name, Value::GameObject(id));
+                    self.define_global(ball_name, Value::GameObject(id));
                 }
                 
                 return Ok(Value::GameObject(id));
@@ -874,7 +1632,7 @@ impl Interpreter {
                 
                 match arg_value {
                     Value::GameObject(id) => {
-                        self.game_objects.destroy_object(id);
+                        self.destroy_object(id);
                         return Ok(Value::String("Object destroyed".to_string()));
                     },
                     Value::String(s) if s.starts_with("cursor:") => {
@@ -894,7 +1652,7 @@ impl Interpreter {
                             // Destroy the first object found (could be enhanced to specify type)
                             if let Some(obj_name) = objects_at_cursor.first() {
                                 if let Some(obj_id) = self.game_objects.find_object_by_name(obj_name) {
-                                    self.game_objects.destroy_object(obj_id);
+                                    self.destroy_object(obj_id);
                                     return Ok(Value::String(format!("Destroyed {} at cursor position", obj_name)));
                                 }
                             }
@@ -913,8 +1671,8 @@ impl Interpreter {
         }
 
         // Check for user-defined functions
-        if let Some(function) = self.environment.get(name).cloned() {
-            if let Value::Function { parameters, body, .. } = function {
+        if let Some(function) = self.lookup(name) {
+            if let Value::Function { parameters, body, closure, .. } = function {
                 if arguments.len() != parameters.len() {
                     return Err(InterpreterError::RuntimeError(
                         format!("Function {} expects {} arguments, got {}", name, parameters.len(), arguments.len())
@@ -927,25 +1685,28 @@ impl Interpreter {
                     arg_values.push(self.evaluate_expression(arg)?);
                 }
 
-                // Save current environment
-                let saved_env = self.environment.clone();
-
-                // Set up function parameters
-                for (param, value) in parameters.iter().zip(arg_values.iter()) {
-                    self.environment.insert(param.clone(), value.clone());
+                // Parameters live in a new scope enclosed by the one the function was defined in
+                let mut scope = Environment::with_enclosing(closure);
+                for (param, value) in parameters.iter().zip(arg_values.into_iter()) {
+                    scope.define(param.clone(), value);
                 }
+                let scope = Rc::new(RefCell::new(scope));
 
                 // Execute function body
-                let result = match self.execute_statement(&body) {
+                self.call_stack.push(CallFrame {
+                    function: name.to_string(),
+                    call_site: self.current_span.unwrap_or_default(),
+                });
+                let result = match body.as_ref() {
+                    Stmt::Block(statements) => self.execute_block(statements, scope),
+                    other => self.execute_block(std::slice::from_ref(other), scope),
+                };
+                self.call_stack.pop();
+                match result {
                     Ok(value) => Ok(value),
                     Err(InterpreterError::Return(value)) => Ok(value),
                     Err(e) => Err(e),
-                };
-
-                // Restore environment
-                self.environment = saved_env;
-
-                result
+                }
             } else {
                 Err(InterpreterError::TypeError(format!("{} is not a function", name)))
             }
@@ -1006,81 +1767,647 @@ impl Interpreter {
                         "Grid x must be a positive integer <= 100".to_string()
                     ));
                 }
-            } else {
-                return Err(InterpreterError::TypeError(
-                    "Grid x must be a number".to_string()
-                ));
+            } else {
+                return Err(InterpreterError::TypeError(
+                    "Grid x must be a number".to_string()
+                ));
+            };
+            let y = if let Value::Number(n) = y_val {
+                if n.fract() == 0.0 && n > 0.0 && n <= 100.0 {
+                    n as u32
+                } else {
+                    return Err(InterpreterError::RuntimeError(
+                        "Grid y must be a positive integer <= 100".to_string()
+                    ));
+                }
+            } else {
+                return Err(InterpreterError::TypeError(
+                    "Grid y must be a number".to_string()
+                ));
+            };
+            let z = if let Value::Number(n) = z_val {
+                if n.fract() == 0.0 && n >= 0.0 {
+                    n as u32
+                } else {
+                    return Err(InterpreterError::RuntimeError(
+                        "Grid center origin z must be a non-negative integer".to_string()
+                    ));
+                }
+            } else {
+                return Err(InterpreterError::TypeError(
+                    "Grid center origin z must be a number".to_string()
+                ));
+            };
+            self.grid_state = Some(GridState::new_with_center(x, y, z));
+            self.physics_engine.update_grid_size(x as f64, y as f64);
+            
+            // Add this line to flag that graphics need updating
+            if self.current_script_owner.is_some() {
+                self.graphics_update_needed = true;
+            }
+            
+            Ok(Value::String(format!("Created {}x{} grid with center origin at {}", x, y, z)))
+        } else {
+            let expected_args = if is_script_context { "2 or 3" } else { "2" };
+            return Err(InterpreterError::RuntimeError(
+                format!("grid() requires exactly {} arguments", expected_args)
+            ));
+        }
+    }
+
+    fn call_tilesize_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.len() != 1 {
+            return Err(InterpreterError::RuntimeError(
+                "tilesize() requires exactly one argument".to_string()
+            ));
+        }
+        
+        let size_value = self.evaluate_expression(&arguments[0])?;
+        
+        match size_value {
+            Value::Number(size) => {
+                if size < 4.0 || size > 100.0 {
+                    return Err(InterpreterError::RuntimeError(
+                        "Tile size must be between 4 and 100 pixels".to_string()
+                    ));
+                }
+                
+                self.define_global("__tile_size".to_string(), Value::Number(size));
+                
+                Ok(Value::String(format!("Tile size set to {} pixels", size as u32)))
+            },
+            _ => {
+                Err(InterpreterError::TypeError(
+                    "tilesize() argument must be a number".to_string()
+                ))
+            }
+        }
+    }
+
+    /// Reads `object.property`. Positions are grid coordinates as used by
+    /// `create ball(x, y)` and `create square(x, y)`, measured to the object's
+    /// centre, and directions are in degrees (0 = right, 90 = down).
+    fn get_object_property(&self, id: u32, property: &str) -> Result<Value, InterpreterError> {
+        match self.game_objects.get_object(id) {
+            Some(GameObject::Ball(ball)) => match property {
+                "x" => Ok(Value::Number(script_coordinate(ball.x))),
+                "y" => Ok(Value::Number(script_coordinate(ball.y))),
+                "speed" => Ok(Value::Number(ball.speed)),
+                "direction" => Ok(Value::Number(ball.direction.to_degrees())),
+                "color" => Ok(Value::String(ball.color.clone())),
+                "volume" => Ok(Value::Number(ball.audio_volume as f64)),
+                "pitch" => Ok(Value::Number(ball.pitch)),
+                "rate" => Ok(Value::Number(ball.playback_rate)),
+                "pan" => Ok(ball.pan.map(Value::Number).unwrap_or(Value::Nil)),
+                "synth" => Ok(ball.synth.as_ref().map(|synth| Value::String(synth.waveform.name().to_string())).unwrap_or(Value::Nil)),
+                "midi_note" => Ok(ball.midi_note.map(|note| Value::Number(note as f64)).unwrap_or(Value::Nil)),
+                "hits" => Ok(Value::Number(ball.get_total_hits() as f64)),
+                "name" => Ok(Value::String(ball.get_friendly_name())),
+                "sample" => Ok(ball.audio_file.clone().map(Value::String).unwrap_or(Value::Nil)),
+                "script" => Ok(ball.script.clone().map(Value::String).unwrap_or(Value::Nil)),
+                _ => Err(InterpreterError::RuntimeError(format!("Balls have no property '{}'", property))),
+            },
+            Some(GameObject::Square(square)) => match property {
+                "x" => Ok(Value::Number(script_coordinate(square.x + SQUARE_HALF_SIZE))),
+                "y" => Ok(Value::Number(script_coordinate(square.y + SQUARE_HALF_SIZE))),
+                "color" => Ok(Value::String(square.color.clone())),
+                "label" => Ok(square.label.as_ref().map(|label| Value::String(label.trim_end().to_string())).unwrap_or(Value::Nil)),
+                "midi_note" => Ok(square.midi_note.map(|note| Value::Number(note as f64)).unwrap_or(Value::Nil)),
+                "hits" => Ok(Value::Number(square.get_total_hits() as f64)),
+                "name" => Ok(Value::String(square.get_friendly_name())),
+                "script" => Ok(square.script.clone().map(Value::String).unwrap_or(Value::Nil)),
+                _ => Err(InterpreterError::RuntimeError(format!("Squares have no property '{}'", property))),
+            },
+            None => Err(InterpreterError::RuntimeError(format!("Object with ID {} not found", id))),
+        }
+    }
+    
+    // Writes `object.property = value`; read-only properties such as hits and name are rejected
+    fn set_object_property(&mut self, id: u32, property: &str, value: Value) -> Result<(), InterpreterError> {
+        let number = |value: &Value| match value.as_number() {
+            Some(n) if n.is_finite() => Ok(n),
+            Some(_) => Err(InterpreterError::RuntimeError(format!("{} must be a finite number", property))),
+            None => Err(InterpreterError::TypeError(format!("{} must be a number", property))),
+        };
+        let text = |value: &Value| match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        // nil clears the note, so the default (or the ball's note) applies again
+        let midi_note = |value: &Value| match value {
+            Value::Nil => Ok(None),
+            _ => {
+                let note = number(value)?;
+                if !(0.0..=127.0).contains(&note) {
+                    return Err(InterpreterError::RuntimeError("midi_note must be between 0 and 127".to_string()));
+                }
+                Ok(Some(note.round() as u8))
+            },
+        };
+        
+        if let Some(ball) = self.game_objects.get_ball_mut(id) {
+            match property {
+                "x" => { let x = number(&value)?; ball.set_position(centre_coordinate(x), ball.y); },
+                "y" => { let y = number(&value)?; ball.set_position(ball.x, centre_coordinate(y)); },
+                "speed" => {
+                    let speed = number(&value)?;
+                    if speed < 0.0 {
+                        return Err(InterpreterError::RuntimeError("speed cannot be negative".to_string()));
+                    }
+                    ball.speed = speed;
+                    ball.set_direction(ball.direction); // Recompute velocity
+                },
+                "direction" => { let degrees = number(&value)?; ball.set_direction(degrees.to_radians()); },
+                "color" => ball.set_color(text(&value)),
+                "volume" => ball.set_audio_volume(number(&value)? as f32),
+                "pitch" => ball.set_pitch(number(&value)?),
+                "rate" => {
+                    let rate = number(&value)?;
+                    if rate <= 0.0 {
+                        return Err(InterpreterError::RuntimeError("rate must be greater than 0".to_string()));
+                    }
+                    ball.set_playback_rate(rate);
+                },
+                "pan" => match value {
+                    // "auto" hands the ball back to the pan mode
+                    Value::String(ref text) if text == "auto" => ball.set_pan(None),
+                    Value::Nil => ball.set_pan(None),
+                    _ => ball.set_pan(Some(number(&value)?)),
+                },
+                "midi_note" => ball.midi_note = midi_note(&value)?,
+                "hits" | "name" | "sample" | "script" | "synth" => {
+                    return Err(InterpreterError::RuntimeError(format!("Ball property '{}' is read-only", property)));
+                },
+                _ => return Err(InterpreterError::RuntimeError(format!("Balls have no property '{}'", property))),
+            }
+            return Ok(());
+        }
+        
+        if let Some(square) = self.game_objects.get_square_mut(id) {
+            match property {
+                "x" => { let x = number(&value)?; square.set_position(centre_coordinate(x) - SQUARE_HALF_SIZE, square.y); },
+                "y" => { let y = number(&value)?; square.set_position(square.x, centre_coordinate(y) - SQUARE_HALF_SIZE); },
+                "color" => square.set_color(text(&value)),
+                "label" => square.set_label(text(&value)),
+                "midi_note" => square.midi_note = midi_note(&value)?,
+                "hits" | "name" | "script" => {
+                    return Err(InterpreterError::RuntimeError(format!("Square property '{}' is read-only", property)));
+                },
+                _ => return Err(InterpreterError::RuntimeError(format!("Squares have no property '{}'", property))),
+            }
+            return Ok(());
+        }
+        
+        Err(InterpreterError::RuntimeError(format!("Object with ID {} not found", id)))
+    }
+
+    fn call_list_function(&mut self, name: &str, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        let expected = if name == "push" { 2 } else { 1 };
+        if arguments.len() != expected {
+            let usage = match name {
+                "push" => "push(list, value)",
+                "pop" => "pop(list)",
+                _ => "len(list)",
+            };
+            return Err(InterpreterError::RuntimeError(format!("{} expects {} argument(s): {}", name, expected, usage)));
+        }
+        
+        let target = self.evaluate_expression(&arguments[0])?;
+        match (name, target) {
+            ("len", Value::List(items)) => Ok(Value::Number(items.borrow().len() as f64)),
+            ("len", Value::String(text)) => Ok(Value::Number(text.chars().count() as f64)),
+            ("push", Value::List(items)) => {
+                let value = self.evaluate_expression(&arguments[1])?;
+                items.borrow_mut().push(value);
+                Ok(Value::Number(items.borrow().len() as f64))
+            },
+            ("pop", Value::List(items)) => Ok(items.borrow_mut().pop().unwrap_or(Value::Nil)),
+            (_, other) => Err(InterpreterError::TypeError(format!("{} expects a list, got {}", name, other.to_string()))),
+        }
+    }
+
+    fn call_tickrate_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.is_empty() {
+            return Ok(Value::String(format!("Tick rate: {} Hz, {} substeps", self.tick_rate, self.physics_engine.substeps)));
+        }
+        if arguments.len() > 2 {
+            return Err(InterpreterError::RuntimeError("tickrate expects 1 or 2 arguments: tickrate(rate) or tickrate(rate, substeps)".to_string()));
+        }
+        
+        let rate = self.evaluate_expression(&arguments[0])?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("Tick rate must be a number".to_string()))?;
+        if rate < 1.0 || rate > 10000.0 {
+            return Err(InterpreterError::RuntimeError("Tick rate must be between 1 and 10000".to_string()));
+        }
+        
+        if arguments.len() == 2 {
+            let substeps = self.evaluate_expression(&arguments[1])?.as_number()
+                .ok_or_else(|| InterpreterError::TypeError("Substeps must be a number".to_string()))?;
+            if substeps < 1.0 {
+                return Err(InterpreterError::RuntimeError("Substeps must be at least 1".to_string()));
+            }
+            self.physics_engine.set_substeps(substeps as u32);
+        }
+        
+        self.tick_rate = rate;
+        self.time_accumulator = 0.0;
+        Ok(Value::String(format!("Tick rate set to {} Hz, {} substeps", self.tick_rate, self.physics_engine.substeps)))
+    }
+
+    fn call_bpm_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.is_empty() {
+            return Ok(Value::Number(self.transport.bpm));
+        }
+        if arguments.len() != 1 {
+            return Err(InterpreterError::RuntimeError("bpm expects 1 argument: bpm(120)".to_string()));
+        }
+        
+        let bpm = self.evaluate_expression(&arguments[0])?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("Tempo must be a number".to_string()))?;
+        if !(1.0..=999.0).contains(&bpm) {
+            return Err(InterpreterError::RuntimeError("Tempo must be between 1 and 999 bpm".to_string()));
+        }
+        
+        self.transport.bpm = bpm;
+        Ok(Value::String(format!("Tempo set to {} bpm", bpm)))
+    }
+
+    fn call_quantize_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.len() > 1 {
+            return Err(InterpreterError::RuntimeError("quantize expects 1 argument: quantize(1/16), or quantize(0) to turn it off".to_string()));
+        }
+        if arguments.is_empty() {
+            return Ok(match self.transport.quantize {
+                Some(fraction) => Value::Number(fraction),
+                None => Value::Number(0.0),
+            });
+        }
+        
+        let fraction = match self.evaluate_expression(&arguments[0])? {
+            Value::Number(fraction) => fraction,
+            Value::String(text) if text == "off" => 0.0,
+            _ => return Err(InterpreterError::TypeError("quantize expects a note length like 1/16, or 0 for off".to_string())),
+        };
+        if fraction < 0.0 || fraction > 4.0 {
+            return Err(InterpreterError::RuntimeError("Quantize must be a note length between 0 (off) and 4 bars".to_string()));
+        }
+        
+        if fraction == 0.0 {
+            self.transport.quantize = None;
+            Ok(Value::String("Quantize off".to_string()))
+        } else {
+            self.transport.quantize = Some(fraction);
+            let division = 1.0 / fraction;
+            if (division - division.round()).abs() < 1e-9 {
+                Ok(Value::String(format!("Quantize set to 1/{} notes", division.round())))
+            } else {
+                Ok(Value::String(format!("Quantize set to {} of a bar", fraction)))
+            }
+        }
+    }
+
+    // osc(9000) listens on localhost port 9000; osc(9000, "127.0.0.1:9001") also
+    // sends collisions to that address; osc("off") closes the port
+    fn call_osc_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(InterpreterError::RuntimeError("osc expects 1 or 2 arguments: osc(port), osc(port, \"host:port\") or osc(\"off\")".to_string()));
+        }
+        
+        let port = match self.evaluate_expression(&arguments[0])? {
+            Value::String(text) if text == "off" => {
+                return Ok(Value::String(match self.osc.take() {
+                    Some(server) => format!("Stopped listening for OSC on port {}", server.port()),
+                    None => "OSC is not running".to_string(),
+                }));
+            },
+            Value::Number(port) if port.fract() == 0.0 && (0.0..=65535.0).contains(&port) => port as u16,
+            other => return Err(InterpreterError::TypeError(format!("osc expects a port number or \"off\", got {}", other.to_string()))),
+        };
+        
+        let target = match arguments.get(1).map(|argument| self.evaluate_expression(argument)).transpose()? {
+            None => None,
+            Some(Value::String(address)) => Some(crate::osc::local_target(&address).map_err(InterpreterError::RuntimeError)?),
+            Some(other) => return Err(InterpreterError::TypeError(format!("osc expects a \"host:port\" target, got {}", other.to_string()))),
+        };
+        
+        // Close the old port first, so osc() can be called again with the same one
+        self.osc = None;
+        let server = OscServer::bind(port, target).map_err(InterpreterError::RuntimeError)?;
+        let destination = target.map(|target| target.to_string()).unwrap_or_else(|| "the last sender".to_string());
+        let message = format!("Listening for OSC on port {}; collisions are sent to {}", server.port(), destination);
+        self.osc = Some(server);
+        Ok(Value::String(message))
+    }
+
+    // export_slices("loop.wav") or export_slices(ball1) writes samples/loop_01.wav, ...;
+    // export_slices("loop.wav", ball3) also loads them into ball3, ball4, ...
+    fn call_export_slices_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.is_empty() || arguments.len() > 2 {
+            return Err(InterpreterError::RuntimeError("export_slices expects 1 or 2 arguments: export_slices(\"loop.wav\") or export_slices(\"loop.wav\", ball1)".to_string()));
+        }
+        
+        let sample_name = match self.evaluate_expression(&arguments[0])? {
+            Value::String(name) => name,
+            Value::GameObject(id) => match self.game_objects.get_object(id) {
+                Some(GameObject::Ball(ball)) => ball.audio_file.clone().ok_or_else(|| InterpreterError::RuntimeError(
+                    format!("{} has no sample loaded", ball.get_friendly_name())
+                ))?,
+                _ => return Err(InterpreterError::TypeError("export_slices expects a sample file or a ball".to_string())),
+            },
+            other => return Err(InterpreterError::TypeError(format!("export_slices expects a sample file or a ball, got {}", other.to_string()))),
+        };
+        
+        // First ball to load the slices into; the rest follow by number
+        let first_ball = match arguments.get(1).map(|argument| self.evaluate_expression(argument)).transpose()? {
+            None => None,
+            Some(Value::GameObject(id)) => match self.game_objects.get_object(id) {
+                Some(GameObject::Ball(ball)) => Some(ball.sequence_number),
+                _ => return Err(InterpreterError::TypeError("export_slices loads slices into balls only".to_string())),
+            },
+            Some(other) => return Err(InterpreterError::TypeError(format!("export_slices expects a ball to load into, got {}", other.to_string()))),
+        };
+        
+        let audio_error = |e: crate::audio_engine::AudioError| InterpreterError::RuntimeError(e.to_string());
+        let sample_key = crate::audio_engine::find_sample_key(&sample_name).map_err(audio_error)?
+            .ok_or_else(|| InterpreterError::RuntimeError(format!("Sample '{}' is not loaded; open it with waveform or sample first", sample_name)))?;
+        let paths = crate::audio_engine::export_slices(&sample_key, project::SAMPLES_DIR).map_err(audio_error)?;
+        
+        let mut message = match (paths.first(), paths.last()) {
+            (Some(first), Some(last)) if paths.len() > 1 => format!("Wrote {} slices: {} ... {}", paths.len(), first, last),
+            (Some(first), _) => format!("Wrote 1 slice: {}", first),
+            _ => "No slices to write".to_string(),
+        };
+        
+        if let Some(first_ball) = first_ball {
+            let mut loaded = Vec::new();
+            for (offset, path) in paths.iter().enumerate() {
+                let ball_name = format!("ball{}", first_ball + offset as u32);
+                let Some(ball_id) = self.game_objects.find_object_by_name(&ball_name) else {
+                    break;
+                };
+                if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
+                    ball.load_audio_file(path).map_err(audio_error)?;
+                    loaded.push(ball_name);
+                }
+            }
+            match (loaded.first(), loaded.last()) {
+                (Some(first), Some(last)) if loaded.len() > 1 => message.push_str(&format!("; loaded into {} - {}", first, last)),
+                (Some(first), _) => message.push_str(&format!("; loaded into {}", first)),
+                _ => message.push_str(&format!("; there is no ball{} to load them into", first_ball)),
+            }
+            if loaded.len() < paths.len() && !loaded.is_empty() {
+                message.push_str(&format!(" ({} slices had no ball)", paths.len() - loaded.len()));
+            }
+        }
+        Ok(Value::String(message))
+    }
+
+    fn call_pan_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.len() > 1 {
+            return Err(InterpreterError::RuntimeError("pan expects 1 argument: pan(\"on\") or pan(\"off\")".to_string()));
+        }
+        if let Some(argument) = arguments.first() {
+            self.pan_mode = match self.evaluate_expression(argument)? {
+                Value::String(text) if text == "on" => true,
+                Value::String(text) if text == "off" => false,
+                Value::Number(n) => n != 0.0,
+                Value::Boolean(b) => b,
+                other => return Err(InterpreterError::TypeError(format!("pan expects \"on\" or \"off\", got {}", other.to_string()))),
             };
-            let y = if let Value::Number(n) = y_val {
-                if n.fract() == 0.0 && n > 0.0 && n <= 100.0 {
-                    n as u32
-                } else {
-                    return Err(InterpreterError::RuntimeError(
-                        "Grid y must be a positive integer <= 100".to_string()
-                    ));
+        }
+        Ok(Value::String(format!("Pan mode {}", if self.pan_mode { "on: collision x sets the stereo position" } else { "off" })))
+    }
+
+    fn call_render_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        if arguments.len() != 2 {
+            return Err(InterpreterError::RuntimeError("render expects 2 arguments: render(\"out.wav\", seconds)".to_string()));
+        }
+        
+        let path = match self.evaluate_expression(&arguments[0])? {
+            Value::String(path) => path,
+            _ => return Err(InterpreterError::TypeError("render expects a file name as first argument".to_string())),
+        };
+        let seconds = self.evaluate_expression(&arguments[1])?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("render expects a number of seconds".to_string()))?;
+        
+        self.render_to_wav(&path, seconds).map(Value::String)
+    }
+    
+    /// Simulates the scene from its current state for `seconds` at the fixed tick
+    /// rate and mixes every triggered sound into a WAV file instead of playing it.
+    /// The scene is restored afterwards, exactly like `play` followed by `stop`.
+    pub fn render_to_wav(&mut self, path: &str, seconds: f64) -> Result<String, InterpreterError> {
+        if self.game_state_manager.is_playing() || self.game_state_manager.is_paused() {
+            return Err(InterpreterError::RuntimeError("Stop playback before rendering".to_string()));
+        }
+        if !seconds.is_finite() || seconds <= 0.0 || seconds > crate::audio_engine::MAX_RENDER_SECONDS {
+            return Err(InterpreterError::RuntimeError(format!(
+                "render length must be more than 0 and at most {} seconds", crate::audio_engine::MAX_RENDER_SECONDS
+            )));
+        }
+        
+        crate::audio_engine::begin_render_capture()
+            .map_err(|e| InterpreterError::RuntimeError(format!("Failed to start render: {}", e)))?;
+        self.execute_play()?;
+        
+        let tick = 1.0 / self.tick_rate;
+        let ticks = (seconds / tick).round() as u64;
+        for _ in 0..ticks {
+            if !self.is_playing() {
+                break;
+            }
+            self.update_physics(tick);
+        }
+        
+        let voices = crate::audio_engine::end_render_capture()
+            .map_err(|e| InterpreterError::RuntimeError(format!("Failed to finish render: {}", e)))?;
+        self.execute_stop()?;
+        
+        crate::audio_engine::render_voices_to_wav(path, &voices, seconds)
+            .map_err(|e| InterpreterError::RuntimeError(format!("Failed to render: {}", e)))?;
+        
+        Ok(format!("Rendered {} seconds ({} sounds) to {}", seconds, voices.len(), path))
+    }
+
+    /// Writes the grid, balls, squares, in-memory scripts and slice markers to
+    /// `projects/<name>.json`. Runtime state such as hit counts is not saved.
+    pub fn save_project(&mut self, name: &str) -> Result<String, InterpreterError> {
+        if self.game_state_manager.is_playing() || self.game_state_manager.is_paused() {
+            return Err(InterpreterError::RuntimeError("Stop playback before saving".to_string()));
+        }
+        
+        let mut project = ProjectFile::new();
+        project.grid = self.grid_state.as_ref().map(|grid| GridData {
+            width: grid.width,
+            height: grid.height,
+            center_origin: grid.center_origin,
+        });
+        
+        let mut sample_keys = Vec::new();
+        for ball in self.game_objects.get_all_balls_mut() {
+            if let Some(ref audio_file) = ball.audio_file {
+                if !sample_keys.contains(audio_file) {
+                    sample_keys.push(audio_file.clone());
                 }
-            } else {
-                return Err(InterpreterError::TypeError(
-                    "Grid y must be a number".to_string()
-                ));
-            };
-            let z = if let Value::Number(n) = z_val {
-                if n.fract() == 0.0 && n >= 0.0 {
-                    n as u32
-                } else {
-                    return Err(InterpreterError::RuntimeError(
-                        "Grid center origin z must be a non-negative integer".to_string()
-                    ));
+            }
+            project.balls.push(BallData {
+                name: ball.get_friendly_name(),
+                x: ball.x,
+                y: ball.y,
+                speed: ball.speed,
+                direction: ball.direction,
+                color: ball.color.clone(),
+                audio_file: ball.audio_file.as_deref().map(project::sample_to_project_path),
+                volume: ball.audio_volume,
+                pitch: ball.pitch,
+                rate: ball.playback_rate,
+                pan: ball.pan,
+                synth: ball.synth.clone(),
+                effects: ball.effects.clone(),
+                midi_note: ball.midi_note,
+                script: ball.script.clone(),
+            });
+        }
+        
+        for square in self.game_objects.get_all_squares() {
+            project.squares.push(SquareData {
+                name: square.get_friendly_name(),
+                x: square.x,
+                y: square.y,
+                color: square.color.clone(),
+                label: square.label.clone(),
+                midi_note: square.midi_note,
+                script: square.script.clone(),
+            });
+        }
+        
+        project.memory_scripts = self.memory_scripts.iter()
+            .map(|(name, content)| (name.clone(), content.clone()))
+            .collect();
+        project.master_effects = self.master_effects.clone();
+        
+        // Markers of every loaded sample, not just those on balls, so slicing work isn't lost
+        if let Ok(mut loaded) = crate::audio_engine::with_audio_engine(|engine| Ok(engine.get_loaded_samples())) {
+            loaded.sort();
+            for key in loaded {
+                if !sample_keys.contains(&key) {
+                    sample_keys.push(key);
                 }
-            } else {
-                return Err(InterpreterError::TypeError(
-                    "Grid center origin z must be a number".to_string()
-                ));
-            };
-            self.grid_state = Some(GridState::new_with_center(x, y, z));
-            self.physics_engine.update_grid_size(x as f64, y as f64);
-            
-            // Add this line to flag that graphics need updating
-            if self.current_script_owner.is_some() {
-                self.graphics_update_needed = true;
             }
-            
-            Ok(Value::String(format!("Created {}x{} grid with center origin at {}", x, y, z)))
-        } else {
-            let expected_args = if is_script_context { "2 or 3" } else { "2" };
-            return Err(InterpreterError::RuntimeError(
-                format!("grid() requires exactly {} arguments", expected_args)
-            ));
         }
+        for key in &sample_keys {
+            let markers = crate::audio_engine::get_sample_markers(key).unwrap_or_default();
+            project.samples.push(SampleData {
+                file: project::sample_to_project_path(key),
+                slice_markers: markers,
+            });
+        }
+        
+        let path = project::project_path(name);
+        project.save(&path).map_err(InterpreterError::RuntimeError)?;
+        Ok(format!("Saved project to {} ({} balls, {} squares)", path.display(), project.balls.len(), project.squares.len()))
     }
-
-    fn call_tilesize_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
-        if arguments.len() != 1 {
-            return Err(InterpreterError::RuntimeError(
-                "tilesize() requires exactly one argument".to_string()
-            ));
+    
+    /// Replaces the current scene with the one saved in `projects/<name>.json`.
+    pub fn load_project(&mut self, name: &str) -> Result<String, InterpreterError> {
+        if self.game_state_manager.is_playing() || self.game_state_manager.is_paused() {
+            return Err(InterpreterError::RuntimeError("Stop playback before loading".to_string()));
         }
         
-        let size_value = self.evaluate_expression(&arguments[0])?;
+        let path = project::project_path(name);
+        let project = ProjectFile::load(&path).map_err(InterpreterError::RuntimeError)?;
         
-        match size_value {
-            Value::Number(size) => {
-                if size < 4.0 || size > 100.0 {
-                    return Err(InterpreterError::RuntimeError(
-                        "Tile size must be between 4 and 100 pixels".to_string()
-                    ));
+        // Drop the old scene, including the names its objects were bound to
+        self.game_objects.clear_all_balls();
+        self.game_objects.clear_all_squares();
+        crate::audio_engine::remove_effect_bus(None);
+        let mut globals = self.globals.borrow().values().clone();
+        globals.retain(|_, value| !matches!(value, Value::GameObject(_)));
+        self.globals.borrow_mut().replace_values(globals);
+        
+        if let Some(ref grid) = project.grid {
+            self.grid_state = Some(match grid.center_origin {
+                Some(center) => GridState::new_with_center(grid.width, grid.height, center),
+                None => GridState::new(grid.width, grid.height),
+            });
+            self.physics_engine.update_grid_size(grid.width as f64, grid.height as f64);
+            self.graphics_update_needed = true;
+        }
+        
+        let mut warnings = Vec::new();
+        
+        // Samples first so markers are in place before balls reference them
+        for sample in &project.samples {
+            let sample_path = project::sample_from_project_path(&sample.file);
+            match crate::audio_engine::load_audio_file(&sample_path) {
+                Ok(key) => {
+                    if !sample.slice_markers.is_empty() {
+                        let _ = crate::audio_engine::set_sample_markers(&key, sample.slice_markers.clone());
+                    }
+                },
+                Err(e) => warnings.push(format!("{}: {}", sample.file, e)),
+            }
+        }
+        
+        for data in &project.balls {
+            let id = self.game_objects.create_ball(data.x, data.y, data.speed, data.direction);
+            if let Some(ball) = self.game_objects.get_ball_mut(id) {
+                if let Some(sequence) = project::sequence_from_name(&data.name, "ball") {
+                    ball.set_sequence_number(sequence);
                 }
-                
-                self.environment.insert("__tile_size".to_string(), Value::Number(size));
-                
-                Ok(Value::String(format!("Tile size set to {} pixels", size as u32)))
-            },
-            _ => {
-                Err(InterpreterError::TypeError(
-                    "tilesize() argument must be a number".to_string()
-                ))
+                ball.set_color(data.color.clone());
+                ball.set_audio_volume(data.volume);
+                ball.set_pitch(data.pitch);
+                ball.set_playback_rate(data.rate);
+                ball.set_pan(data.pan);
+                ball.effects = data.effects.clone();
+                ball.midi_note = data.midi_note;
+                ball.script = data.script.clone();
+                if let Some(ref file) = data.audio_file {
+                    // Already decoded above, this only points the ball at it
+                    let sample_path = project::sample_from_project_path(file);
+                    if let Err(e) = ball.load_audio_file(&sample_path) {
+                        warnings.push(format!("{}: {}", file, e));
+                    }
+                }
+                // After the sample, since loading one clears the synth
+                ball.synth = data.synth.clone().map(SynthSettings::clamped);
+            }
+            if let Some(ball_name) = self.game_objects.get_ball_name(id) {
+                self.define_global(ball_name, Value::GameObject(id));
+            }
+        }
+        
+        for data in &project.squares {
+            let id = self.game_objects.create_square(data.x, data.y);
+            if let Some(square) = self.game_objects.get_square_mut(id) {
+                if let Some(sequence) = project::sequence_from_name(&data.name, "square") {
+                    square.set_sequence_number(sequence);
+                }
+                square.set_color(data.color.clone());
+                square.label = data.label.clone(); // Stored already formatted
+                square.midi_note = data.midi_note;
+                square.script = data.script.clone();
+            }
+            if let Some(square_name) = self.game_objects.get_square_name(id) {
+                self.define_global(square_name, Value::GameObject(id));
             }
         }
+        
+        self.memory_scripts = project.memory_scripts.into_iter().collect();
+        
+        if !project.master_effects.is_empty() || !self.master_effects.is_empty() {
+            if let Err(e) = crate::audio_engine::set_master_effects(project.master_effects.clone()) {
+                warnings.push(format!("master effects: {}", e));
+            }
+        }
+        self.master_effects = project.master_effects;
+        
+        let mut message = format!("Loaded project {} ({} balls, {} squares)", path.display(), project.balls.len(), project.squares.len());
+        if !warnings.is_empty() {
+            message.push_str(&format!("\nMissing samples: {}", warnings.join(", ")));
+        }
+        Ok(message)
     }
 
     fn call_font_size_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
@@ -1100,7 +2427,7 @@ impl Interpreter {
                     ));
                 }
                 
-                self.environment.insert("__font_size".to_string(), Value::Number(size));
+                self.define_global("__font_size".to_string(), Value::Number(size));
                 
                 Ok(Value::String(format!("Font size set to {}px", size as u32)))
             },
@@ -1164,8 +2491,17 @@ impl Interpreter {
             }
         };
 
-        // Open file dialog to select audio file
-        let file_path = match self.open_audio_file_dialog() {
+        // Use an explicit path if given (sample(ball1, "kick.wav")), otherwise open a file dialog
+        let explicit_path = if arguments.len() >= 2 && !matches!(target_value, Value::Number(_)) {
+            match self.evaluate_expression(&arguments[1])? {
+                Value::String(path) => Some(path),
+                _ => return Err(InterpreterError::TypeError("sample file path must be a string".to_string())),
+            }
+        } else {
+            None
+        };
+        
+        let file_path = match explicit_path.or_else(|| self.open_audio_file_dialog()) {
             Some(path) => path,
             None => {
                 return Ok(Value::String("File selection cancelled".to_string()));
@@ -1189,6 +2525,72 @@ impl Interpreter {
         }
     }
 
+    // synth(ball1, "saw", 220, attack, decay, sustain, release, hold): everything after the waveform is optional
+    fn call_synth_function(&mut self, arguments: &[Expr]) -> Result<Value, InterpreterError> {
+        const USAGE: &str = "synth(ball1, \"sine\"|\"square\"|\"saw\"|\"noise\", frequency, attack, decay, sustain, release, hold)";
+        if arguments.len() < 2 || arguments.len() > 8 {
+            return Err(InterpreterError::RuntimeError(format!("synth expects 2 to 8 arguments: {}", USAGE)));
+        }
+        
+        let ball_id = match self.evaluate_expression(&arguments[0])? {
+            Value::GameObject(id) if self.game_objects.is_ball(id) => id,
+            Value::String(ref s) if s.starts_with("cursor") => self.game_objects.find_ball_at_position(self.cursor_x, self.cursor_y)
+                .ok_or_else(|| InterpreterError::RuntimeError("No ball found at cursor position".to_string()))?,
+            Value::String(ref name) => self.game_objects.find_object_by_name(name)
+                .filter(|&id| self.game_objects.is_ball(id))
+                .ok_or_else(|| InterpreterError::RuntimeError(format!("Ball '{}' not found", name)))?,
+            _ => return Err(InterpreterError::TypeError("synth expects a ball as first argument".to_string())),
+        };
+        
+        let waveform_name = match self.evaluate_expression(&arguments[1])? {
+            Value::String(name) => name,
+            other => return Err(InterpreterError::TypeError(format!("synth waveform must be a string, got {}", other.to_string()))),
+        };
+        let ball_name = self.game_objects.get_ball_name(ball_id).unwrap_or_else(|| format!("ball{}", ball_id));
+        
+        if waveform_name == "off" {
+            if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
+                ball.synth = None;
+            }
+            return Ok(Value::String(format!("Synth removed from {}", ball_name)));
+        }
+        
+        let waveform = Waveform::from_name(&waveform_name)
+            .ok_or_else(|| InterpreterError::RuntimeError(format!("Unknown waveform '{}': use sine, square, saw or noise", waveform_name)))?;
+        
+        let mut values = Vec::new();
+        for argument in &arguments[2..] {
+            let value = self.evaluate_expression(argument)?.as_number()
+                .ok_or_else(|| InterpreterError::TypeError(format!("synth settings must be numbers: {}", USAGE)))?;
+            if !value.is_finite() {
+                return Err(InterpreterError::RuntimeError("synth settings must be finite numbers".to_string()));
+            }
+            if value < 0.0 {
+                return Err(InterpreterError::RuntimeError("synth settings cannot be negative".to_string()));
+            }
+            values.push(value);
+        }
+        
+        let mut settings = SynthSettings::new(waveform);
+        let fields = [
+            &mut settings.frequency, &mut settings.attack, &mut settings.decay,
+            &mut settings.sustain, &mut settings.release, &mut settings.hold,
+        ];
+        for (field, value) in fields.into_iter().zip(values) {
+            *field = value;
+        }
+        if settings.frequency < 1.0 || settings.frequency > 20000.0 {
+            return Err(InterpreterError::RuntimeError("synth frequency must be between 1 and 20000 Hz".to_string()));
+        }
+        let settings = settings.clamped();
+        
+        let message = format!("{} plays a {} synth at {} Hz", ball_name, waveform.name(), settings.frequency);
+        if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
+            ball.synth = Some(settings);
+        }
+        Ok(Value::String(message))
+    }
+
     fn open_audio_file_dialog(&self) -> Option<String> {
         use rfd::FileDialog;
         
@@ -1212,6 +2614,61 @@ impl Interpreter {
     - sample(0, 0) - Load audio into ball at coordinates
     - sample(cursor) - Load audio into ball at cursor
     - sample(ball1) - Load audio into specific ball
+    - sample(ball1, "kick.wav") - Load a file without the dialog
+  synth(ball1, "saw") - Play a synth note instead of a sample: sine, square, saw or noise
+    - synth(ball1, "sine", 220, 0.01, 0.1, 0.6, 0.3, 0.1) - Frequency (Hz), attack,
+      decay, sustain level, release and hold time (seconds, up to 30 each); trailing settings optional
+    - synth(ball1, "off") - Go back to the ball's sample
+  tickrate(rate, substeps) - Set fixed physics tick rate (default 240)
+  render("out.wav", seconds) - Render the scene offline to a WAV file
+  polyphony(voices) - Limit simultaneous sounds (oldest is stolen)
+  bpm(120) - Set the tempo (default 120)
+  quantize(1/16) - Start collision sounds on the next 1/16 note; quantize(0) turns it off
+  beat, bar - Current beat in the bar (1-4) and bar number, counting from 1 at play
+  for i in 1..8 { ... } - Repeat with i = 1, 2, ... 8
+  let steps = [1, 3, 2] - Lists; steps[0] is the first item
+    - len(list), push(list, value), pop(list), for s in steps { ... }
+    - slice steps, fx ball1 delay settings - slice and fx also take their numbers from lists
+  ball1.speed, self.hits, square1.label - Read object properties
+    - balls: x, y, speed, direction (degrees), color, volume, pitch, rate, pan, midi_note, hits, name, sample, synth, script
+    - squares: x, y, color, label, midi_note, hits, name, script
+    - x and y are the cell an object is centred on, as in create, for balls and squares alike
+  ball1.volume = 0.5 - Set x, y, speed, direction, color, volume, pitch, rate, pan, midi_note or label
+  set pitch ball1 +7 - Shift the ball's sample by semitones (resampled, 0 = original)
+  set rate ball1 0.5 - Play the ball's sample at half speed (and an octave down)
+    - Hit scripts and 'on hit' run before the ball's sound, so they can pitch each hit:
+      on hit { set pitch ball notes[ball.hits % len(notes)] }
+  slice 1 2 3 4 pitch 0 7 12 7 - Slice sequence with a pitch in semitones per step
+  slice into 16 ball1 - Mark 16 equal slices on ball1's sample (or "loop.wav"; the script's ball if left out)
+  waveform("loop.wav") - Edit slice markers: Space adds/removes, Enter plays a slice, Esc keeps them
+    - A slices at every hit (- and = change the sensitivity); 1 6 D slices into 16 equal parts
+    - Z snaps markers to zero crossings; G snaps them to a 1/16 grid, 1 2 0 B sets the loop's BPM
+  export_slices("loop.wav") - Write each slice to samples/loop_01.wav, loop_02.wav, ...
+    - Replaces the files of an earlier export (listed in samples/loop.wav.slices.json)
+    - export_slices("loop.wav", ball1) - Also load the slices into ball1, ball2, ... in order
+  fx ball1 delay 0.25 0.4 0.5 - Add or change an effect on a ball's sounds (time, feedback, mix)
+    - fx ball1 lowpass 800 2 / fx ball1 highpass 300 - Filter cutoff (Hz) and resonance
+    - fx ball1 reverb 0.8 0.3 - Reverb size and mix (0-1)
+    - fx master reverb 0.5 - Effects on everything; fx ball1 delay off removes one, fx ball1 off all
+    - Numbers can be expressions, so 'on hit' can change them per hit
+  pan("on") - Pan collision sounds by where on the grid they happen; pan("off") centres them
+  set pan ball1 -0.5 - Fix a ball's pan (-1 left to 1 right); set pan ball1 "auto" undoes it
+  midi_record start - Record every collision as a MIDI note (a track per ball; channel 10 is skipped)
+    - midi_record start speed - Velocity from ball speed instead of volume
+    - square1.midi_note = 36 - Note for hits on square1; otherwise the ball's midi_note, or 60
+    - midi_record stop "take1.mid" - Write the recording as a Standard MIDI File
+  osc(9000) - Listen for OSC messages on localhost port 9000; osc("off") stops
+    - /cant/cmd "set speed ball1 +2" runs a command; /cant/play, /cant/pause, /cant/stop
+    - osc(9000, "127.0.0.1:9001") - Send /cant/collision ball other hits there (default: the last sender)
+  on wall { ... } - Run when a ball hits a wall (ball is the ball)
+  on hit ball2 { ... } - Run on collisions, optionally only those involving ball2
+    - Two balls colliding is one hit, with the lower-numbered ball as ball and the other as other
+  on tick / on play / on stop { ... } - Run every physics step, on play, on stop
+    - In an object's script, handlers only fire for that object and self is the object
+  a and b, a or b, a % b - Logic (short-circuit) and modulo
+    - in, and, or are reserved words and can no longer be used as variable names
+  save("name") - Save the scene to projects/name.json
+  load("name") - Load a scene saved with save()
   clear - Clear the grid
   help - Show this help
   
@@ -1347,9 +2804,11 @@ Controls:
                     self.save_script_to_memory(script_id, script_content.clone());
                 }
             } else if target_id > 0 {
-                // Save script to the target square (existing behavior)
+                // Save script to the target square or ball
                 if let Some(square) = self.game_objects.get_square_mut(target_id) {
                     square.set_script(script_content);
+                } else if let Some(ball) = self.game_objects.get_ball_mut(target_id) {
+                    ball.set_script(script_content);
                 }
             }
         }
@@ -1406,6 +2865,7 @@ Controls:
 
     fn execute_clear_balls(&mut self) -> Result<Value, InterpreterError> {
         let count = self.game_objects.clear_all_balls();
+        crate::audio_engine::remove_effect_bus(None);
         Ok(Value::String(format!("Cleared {} ball(s)", count)))
     }
 
@@ -1528,6 +2988,124 @@ fn execute_set_speed(&mut self, object_name: &str, speed_mod: &SpeedModification
     Ok(Value::String(operation_desc))
 }
 
+// set pitch / set rate: the same as assigning the property, with cursor support like the other set commands
+fn execute_set_property(&mut self, object_name: &str, property: &str, value: &Expr) -> Result<Value, InterpreterError> {
+    let object_id = self.resolve_command_target(object_name)?;
+    let value = self.evaluate_expression(value)?;
+    self.set_object_property(object_id, property, value)?;
+    let new_value = self.get_object_property(object_id, property)?;
+    Ok(Value::String(format!("Set {} of {} to {}", property, object_name, new_value.to_string())))
+}
+
+// Object named by a command such as `set pitch ball1` or `fx self`
+fn resolve_command_target(&self, object_name: &str) -> Result<u32, InterpreterError> {
+    Ok(match object_name {
+        "cursor" => {
+            let object_names_at_cursor = self.game_objects.find_objects_at_grid_with_names(self.cursor_x, self.cursor_y);
+            let first_object_name = object_names_at_cursor.first()
+                .ok_or_else(|| InterpreterError::RuntimeError("No object found at cursor position".to_string()))?;
+            self.game_objects.find_object_by_name(first_object_name)
+                .ok_or_else(|| InterpreterError::RuntimeError(format!("Object '{}' not found", first_object_name)))?
+        },
+        "self" => self.current_script_owner
+            .ok_or_else(|| InterpreterError::RuntimeError("'self' can only be used within object scripts".to_string()))?,
+        // An object name, or a variable holding an object such as `ball` in handlers
+        _ => match self.game_objects.find_object_by_name(object_name) {
+            Some(id) => id,
+            None => match self.lookup(object_name) {
+                Some(Value::GameObject(id)) => id,
+                _ => return Err(InterpreterError::RuntimeError(format!("Object '{}' not found", object_name))),
+            },
+        },
+    })
+}
+
+// fx <ball|master> <effect> <numbers...>: add the effect, or update it if the chain already has one
+fn execute_fx(&mut self, target: &str, effect: &str, arguments: &[Expr], remove: bool) -> Result<Value, InterpreterError> {
+    // Settings can be spelled out or come from a list: fx ball1 delay settings
+    let values = self.evaluate_number_sequence(arguments)?;
+    if values.iter().any(|value| !value.is_finite()) {
+        return Err(InterpreterError::RuntimeError(format!("{} settings must be finite numbers", effect)));
+    }
+    let settings = if effect == "off" || remove {
+        None
+    } else {
+        Some(EffectSettings::from_command(effect, &values).map_err(InterpreterError::RuntimeError)?)
+    };
+    
+    let mut chain = if target == "master" {
+        self.master_effects.clone()
+    } else {
+        let ball_id = self.resolve_command_target(target)?;
+        match self.game_objects.get_object(ball_id) {
+            Some(GameObject::Ball(ball)) => ball.effects.clone(),
+            _ => return Err(InterpreterError::RuntimeError(format!("{} is not a ball; effects go on balls or master", target))),
+        }
+    };
+    
+    let message = match settings {
+        None if effect == "off" => {
+            chain.clear();
+            format!("Removed all effects from {}", target)
+        },
+        None => {
+            chain.retain(|existing| existing.kind() != effect);
+            format!("Removed {} from {}", effect, target)
+        },
+        Some(settings) => {
+            let message = format!("{}: {}", target, settings.describe());
+            match chain.iter_mut().find(|existing| existing.kind() == settings.kind()) {
+                Some(existing) => *existing = settings,
+                None => chain.push(settings),
+            }
+            message
+        },
+    };
+    
+    if target == "master" {
+        crate::audio_engine::set_master_effects(chain.clone())
+            .map_err(|e| InterpreterError::RuntimeError(e.to_string()))?;
+        self.master_effects = chain;
+    } else {
+        let ball_id = self.resolve_command_target(target)?;
+        if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
+            ball.effects = chain;
+        }
+    }
+    Ok(Value::String(message))
+}
+
+// midi_record start [volume|speed] / midi_record stop "file.mid"
+fn execute_midi_record(&mut self, start: bool, velocity: Option<&str>, path: Option<&Expr>) -> Result<Value, InterpreterError> {
+    if start {
+        if self.midi_recording.is_some() {
+            return Err(InterpreterError::RuntimeError("Already recording MIDI; use midi_record stop \"file.mid\" first".to_string()));
+        }
+        let source = match velocity {
+            None => VelocitySource::Volume,
+            Some(name) => VelocitySource::from_name(name).ok_or_else(|| InterpreterError::RuntimeError(
+                format!("Unknown velocity source '{}': use volume or speed", name)
+            ))?,
+        };
+        // Collisions are timed from play, so a recording started while stopped begins at 0
+        let start_time = if self.is_playing() || self.game_state_manager.is_paused() { self.simulation_time } else { 0.0 };
+        self.midi_recording = Some(MidiRecording::new(start_time, &self.transport, source));
+        return Ok(Value::String(format!("Recording collisions as MIDI (velocity from {})", source.name())));
+    }
+    
+    let path = match path.map(|path| self.evaluate_expression(path)).transpose()? {
+        Some(Value::String(path)) => path,
+        _ => return Err(InterpreterError::TypeError("midi_record stop expects a file name: midi_record stop \"take1.mid\"".to_string())),
+    };
+    let recording = self.midi_recording.as_ref()
+        .ok_or_else(|| InterpreterError::RuntimeError("Not recording MIDI; use midi_record start".to_string()))?;
+    // The take is kept until it is written, so a failed write can be retried with another file name
+    recording.write(std::path::Path::new(&path)).map_err(InterpreterError::RuntimeError)?;
+    let message = format!("Wrote {} notes to {}", recording.note_count(), path);
+    self.midi_recording = None;
+    Ok(Value::String(message))
+}
+
 fn execute_script_command(&mut self, object_name: &str, arguments: &[Expr]) -> Result<Value, InterpreterError> {
     // Handle script(new) for creating blank scripts
     if object_name == "new" {
@@ -1579,8 +3157,12 @@ fn execute_script_command(&mut self, object_name: &str, arguments: &[Expr]) -> R
         let existing_script = square.get_script().map(|s| s.to_string());
         self.script_editor = Some(ScriptEditor::new(object_id, existing_script));
         Ok(Value::String("Script editor opened".to_string()))
+    } else if let Some(ball) = self.game_objects.get_ball_mut(object_id) {
+        let existing_script = ball.get_script().map(|s| s.to_string());
+        self.script_editor = Some(ScriptEditor::new(object_id, existing_script));
+        Ok(Value::String("Script editor opened".to_string()))
     } else {
-        Err(InterpreterError::RuntimeError("Only squares can have scripts".to_string()))
+        Err(InterpreterError::RuntimeError("Only squares and balls can have scripts".to_string()))
     }
 }
 
@@ -1671,6 +3253,7 @@ fn execute_collision_script(&mut self, id1: u32, id2: u32) {
         if let Some((ball_id, square_id)) = collision_info {
             // Set the script execution context
             self.current_script_owner = Some(square_id);
+            let previous_ball = self.colliding_ball.replace(ball_id);
             
             // Get script content and hit counts
             let script_content = if let Some(square) = self.game_objects.get_square_mut(square_id) {
@@ -1688,31 +3271,62 @@ fn execute_collision_script(&mut self, id1: u32, id2: u32) {
                 } else { 0 };
                 
                 // Set up script environment
-                self.environment.insert("hits".to_string(), Value::Number(total_hits as f64));
-                self.environment.insert(format!("hits({})", ball_id), Value::Number(ball_hits as f64));
+                self.environment.borrow_mut().define("hits".to_string(), Value::Number(total_hits as f64));
+                self.environment.borrow_mut().define(format!("hits({})", ball_id), Value::Number(ball_hits as f64));
                 // Add the specific ball-square hit count for proper "ball1 hits self 3" evaluation
-                self.environment.insert(format!("hits({},{})", ball_id, square_id), Value::Number(ball_hits as f64));
+                self.environment.borrow_mut().define(format!("hits({},{})", ball_id, square_id), Value::Number(ball_hits as f64));
                 
                 // Check for slice array playback based on hit count
-                self.try_play_collision_slice(square_id, ball_hits);
+                self.try_play_collision_slice(square_id, ball_id, ball_hits);
                 
                 // Parse and execute script commands
                 let cursor_x = self.cursor_x;
                 let cursor_y = self.cursor_y;
-                if let Err(e) = self.execute_script_block(&script, cursor_x, cursor_y) {
-                    eprintln!("Script execution error: {}", e);
-                }
+                // Failures are already reported to the console
+                let _ = self.execute_script_block(&script, cursor_x, cursor_y);
                 
                 // Clean up environment and context
-                self.environment.remove("hits");
-                self.environment.remove(&format!("hits({})", ball_id));
-                self.environment.remove(&format!("hits({},{})", ball_id, square_id));
+                self.environment.borrow_mut().remove("hits");
+                self.environment.borrow_mut().remove(&format!("hits({})", ball_id));
+                self.environment.borrow_mut().remove(&format!("hits({},{})", ball_id, square_id));
                 self.current_script_owner = None;  // Clear script context
             }
+            self.colliding_ball = previous_ball;
+        }
+    }
+
+    fn execute_ball_collision_script(&mut self, ball_id: u32, other_ball_id: u32) {
+        let script_content = if let Some(ball) = self.game_objects.get_ball_mut(ball_id) {
+            ball.get_script().map(|s| s.to_string())
+        } else { None };
+        
+        if let Some(script) = script_content {
+            // Set the script execution context to the ball itself
+            self.current_script_owner = Some(ball_id);
+            
+            let (total_hits, other_hits) = if let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) {
+                (ball.get_total_hits(), ball.get_hit_count(other_ball_id))
+            } else { (0, 0) };
+            
+            // Set up script environment so "ball2 hits self 3" works from the ball's script
+            self.environment.borrow_mut().define("hits".to_string(), Value::Number(total_hits as f64));
+            self.environment.borrow_mut().define(format!("hits({})", other_ball_id), Value::Number(other_hits as f64));
+            self.environment.borrow_mut().define(format!("hits({},{})", other_ball_id, ball_id), Value::Number(other_hits as f64));
+            
+            let cursor_x = self.cursor_x;
+            let cursor_y = self.cursor_y;
+            // Failures are already reported to the console
+            let _ = self.execute_script_block(&script, cursor_x, cursor_y);
+            
+            // Clean up environment and context
+            self.environment.borrow_mut().remove("hits");
+            self.environment.borrow_mut().remove(&format!("hits({})", other_ball_id));
+            self.environment.borrow_mut().remove(&format!("hits({},{})", other_ball_id, ball_id));
+            self.current_script_owner = None;
         }
     }
 
-    fn try_play_collision_slice(&mut self, square_id: u32, hit_count: u32) {
+    fn try_play_collision_slice(&mut self, square_id: u32, ball_id: u32, hit_count: u32) {
         // Generate slice array name based on square ID
         let slice_array_name = format!("square_{}_slice", square_id);
         
@@ -1720,7 +3334,13 @@ fn execute_collision_script(&mut self, id1: u32, id2: u32) {
         // Use hit_count as the slice index (1-based indexing)
         let sequence = vec![hit_count as f64];
         
-        if let Err(e) = crate::audio_engine::play_slice_array(&slice_array_name) {
+        // Slices are cut from the ball's sample, so they follow its pitch and rate
+        let rate = match self.game_objects.get_object(ball_id) {
+            Some(GameObject::Ball(ball)) => ball.get_effective_rate(),
+            _ => 1.0,
+        };
+        
+        if let Err(e) = crate::audio_engine::play_slice_array(&slice_array_name, rate, self.collision_pan) {
             // If slice array doesn't exist or slice index is out of bounds, silently continue
             // This allows squares to work normally even without slice arrays configured
             println!("Debug: Could not play slice for square {}, hit {}: {}", square_id, hit_count, e);
@@ -1734,27 +3354,37 @@ fn execute_script_block(&mut self, script_content: &str, cursor_x: u32, cursor_y
     
     // Parse the entire script as proper AST statements instead of extracting string commands
     let mut lexer = Lexer::new(script_content);
-    let tokens = lexer.tokenize().map_err(|e| {
-        eprintln!("Script tokenization error: {}", e);
-        InterpreterError::LexerError(e)
-    })?;
+    let tokens = match lexer.tokenize() {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            let error = InterpreterError::LexerError(e);
+            self.report_script_error(&error);
+            return Err(error);
+        }
+    };
     
     let mut parser = Parser::new(tokens);
-    let program = parser.parse().map_err(|e| {
-        eprintln!("Script parsing error: {}", e);
-        InterpreterError::ParseError(e)
-    })?;
+    let program = match parser.parse() {
+        Ok(program) => program,
+        Err(e) => {
+            let error = InterpreterError::ParseError(e);
+            self.report_script_error(&error);
+            return Err(error);
+        }
+    };
     
-    // Execute each statement in the script
+    // Execute each statement in the script, errors located even when it is one line long
+    let previous_locate = std::mem::replace(&mut self.locate_errors, true);
     for statement in program.statements {
         println!("Debug: Executing statement: {:?}", statement);
         if let Err(e) = self.execute_statement(&statement) {
-            eprintln!("Error executing script statement: {}", e);
-            // Continue executing other statements even if one fails
+            // Report it in the console and continue executing other statements
+            self.report_script_error(&e);
         } else {
             println!("Debug: Statement executed successfully");
         }
     }
+    self.locate_errors = previous_locate;
     
     Ok(())
 }
@@ -1842,7 +3472,68 @@ pub fn is_verbose_mode(&self) -> bool {
         }
     }
 
-    fn execute_slice_command(&mut self, sequence: &[f64]) -> Result<Value, InterpreterError> {
+    // Evaluate command arguments to numbers, flattening any lists among them
+    fn evaluate_number_sequence(&mut self, expressions: &[Expr]) -> Result<Vec<f64>, InterpreterError> {
+        let mut numbers = Vec::new();
+        for expr in expressions {
+            match self.evaluate_expression(expr)? {
+                Value::Number(n) => numbers.push(n),
+                Value::List(items) => {
+                    for item in items.borrow().iter() {
+                        numbers.push(item.as_number()
+                            .ok_or_else(|| InterpreterError::TypeError(format!("Expected a list of numbers, found {}", item.to_string())))?);
+                    }
+                },
+                other => return Err(InterpreterError::TypeError(format!("Expected a number or list, found {}", other.to_string()))),
+            }
+        }
+        Ok(numbers)
+    }
+
+    // slice into 16 [ball1 | "loop.wav"]: replace a sample's markers with equal slices and
+    // save them next to it, as Esc does in the waveform editor
+    fn execute_slice_into(&mut self, divisions: &Expr, target: Option<&Expr>) -> Result<Value, InterpreterError> {
+        let divisions = self.evaluate_expression(divisions)?.as_number()
+            .ok_or_else(|| InterpreterError::TypeError("slice into expects a number of slices: slice into 16".to_string()))?;
+        if !(1.0..=999.0).contains(&divisions) || divisions.fract() != 0.0 {
+            return Err(InterpreterError::RuntimeError("slice into expects a whole number of slices from 1 to 999".to_string()));
+        }
+        
+        // Without a target: the ball running the script, or the ball that hit the square running it
+        let ball_id = match target.map(|target| self.evaluate_expression(target)).transpose()? {
+            Some(Value::String(name)) => return self.slice_sample_into(&name, divisions as usize),
+            Some(Value::GameObject(id)) => id,
+            Some(other) => return Err(InterpreterError::TypeError(format!("slice into expects a ball or a sample file, got {}", other.to_string()))),
+            None => self.current_script_owner
+                .filter(|&owner| matches!(self.game_objects.get_object(owner), Some(GameObject::Ball(_))))
+                .or(self.colliding_ball)
+                .ok_or_else(|| InterpreterError::RuntimeError("slice into needs a ball or sample outside scripts: slice into 16 ball1".to_string()))?,
+        };
+        let sample_name = match self.game_objects.get_object(ball_id) {
+            Some(GameObject::Ball(ball)) => ball.audio_file.clone().ok_or_else(|| InterpreterError::RuntimeError(
+                format!("{} has no sample loaded", ball.get_friendly_name())
+            ))?,
+            _ => return Err(InterpreterError::TypeError("slice into expects a ball or a sample file".to_string())),
+        };
+        self.slice_sample_into(&sample_name, divisions as usize)
+    }
+    
+    fn slice_sample_into(&self, sample_name: &str, divisions: usize) -> Result<Value, InterpreterError> {
+        let audio_error = |e: crate::audio_engine::AudioError| InterpreterError::RuntimeError(e.to_string());
+        let sample_key = crate::audio_engine::find_sample_key(sample_name).map_err(audio_error)?
+            .ok_or_else(|| InterpreterError::RuntimeError(format!("Sample '{}' is not loaded; open it with waveform or sample first", sample_name)))?;
+        let (frames, sample_rate) = crate::audio_engine::get_sample_frames(&sample_key).map_err(audio_error)?;
+        let markers: Vec<f64> = crate::waveform_editor::equal_divisions(frames, divisions).iter()
+            .map(|&frame| frame as f64 / sample_rate.max(1) as f64)
+            .collect();
+        let count = markers.len();
+        crate::audio_engine::set_sample_markers(&sample_key, markers)
+            .and_then(|_| crate::audio_engine::save_sample_markers(&sample_key))
+            .map_err(audio_error)?;
+        Ok(Value::String(format!("Sliced {} into {} equal parts", sample_name, count)))
+    }
+
+    fn execute_slice_command(&mut self, sequence: &[f64], pitches: &[f64]) -> Result<Value, InterpreterError> {
         // Convert f64 sequence to usize indices (subtract 1 for 0-based indexing)
         let indices: Vec<usize> = sequence.iter()
             .map(|&n| if n >= 1.0 { (n as usize).saturating_sub(1) } else { 0 })
@@ -1857,7 +3548,7 @@ pub fn is_verbose_mode(&self) -> bool {
                         let name = format!("square_{}_slice", square.id);
                         // For squares, we need to find the colliding ball's audio file
                         // Check if there's a collision context with a ball
-                        let sample_key = if let Some(ball_id) = self.get_colliding_ball_id(square.id) {
+                        let sample_key = if let Some(ball_id) = self.get_colliding_ball_id() {
                             if let Some(GameObject::Ball(ball)) = self.game_objects.get_object(ball_id) {
                                 ball.audio_file.clone().unwrap_or_else(|| {
                                     println!("Warning: Colliding ball {} has no audio file loaded, using default", ball.get_friendly_name());
@@ -1925,7 +3616,9 @@ pub fn is_verbose_mode(&self) -> bool {
         }
         
         // Create the slice array using the audio engine
-        match crate::audio_engine::create_slice_array(array_name.clone(), sample_key.clone(), indices) {
+        let created = crate::audio_engine::create_slice_array(array_name.clone(), sample_key.clone(), indices)
+            .and_then(|_| crate::audio_engine::set_slice_pitches(&array_name, pitches.to_vec()));
+        match created {
             Ok(()) => {
                 println!("Debug: Created slice array '{}' with sample '{}' and sequence: {:?}", array_name, sample_key, sequence);
                 Ok(Value::String(format!("Created slice array: {}", array_name)))
@@ -1939,22 +3632,9 @@ pub fn is_verbose_mode(&self) -> bool {
     }
 
     // Helper function to get the ball ID that's currently colliding with a square
-    fn get_colliding_ball_id(&self, square_id: u32) -> Option<u32> {
-        // Check environment variables for collision context
-        for (key, value) in &self.environment {
-            if key.starts_with("hits(") && key.contains(&format!(",{}", square_id)) {
-                // Extract ball ID from "hits(ball_id,square_id)" format
-                if let Some(start) = key.find('(') {
-                    if let Some(comma) = key.find(',') {
-                        let ball_id_str = &key[start + 1..comma];
-                        if let Ok(ball_id) = ball_id_str.parse::<u32>() {
-                            return Some(ball_id);
-                        }
-                    }
-                }
-            }
-        }
-        None
+    fn get_colliding_ball_id(&self) -> Option<u32> {
+        // Set for the whole script, so nested blocks and function bodies see it too
+        self.colliding_ball
     }
 
     fn execute_waveform_command(&mut self, target: &Option<String>) -> Result<Value, InterpreterError> {
@@ -2090,4 +3770,104 @@ fn execute_label(&mut self, object_name: &str, arguments: &[Expr], text: &str) -
     }
 }
 
+}
+
+// Squares are stored by their top-left corner and span one cell
+const SQUARE_HALF_SIZE: f64 = 0.5;
+
+// Scripts see the centre of cell n as n, for balls and squares alike, so an
+// object centred in a cell reads back the coordinates it was created with
+fn script_coordinate(centre: f64) -> f64 {
+    centre - 0.5
+}
+
+fn centre_coordinate(script: f64) -> f64 {
+    script + 0.5
+}
+
+// Numbers from start to end inclusive, counting down when start > end
+fn range_values(start: f64, end: f64) -> impl Iterator<Item = f64> {
+    let step = if start <= end { 1.0 } else { -1.0 };
+    let count = ((end - start).abs().floor() as usize).saturating_add(1);
+    (0..count).map(move |index| start + step * index as f64)
+}
+
+// Resolve a 0-based index, negative counting back from the end
+fn list_index(index: &Value, len: usize) -> Result<usize, InterpreterError> {
+    let index = index.as_number()
+        .ok_or_else(|| InterpreterError::TypeError("List index must be a number".to_string()))?;
+    let position = if index < 0.0 { len as f64 + index.floor() } else { index.floor() };
+    if position < 0.0 || position >= len as f64 {
+        return Err(InterpreterError::RuntimeError(format!("Index {} out of range for length {}", index, len)));
+    }
+    Ok(position as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpreter() -> Interpreter {
+        crate::audio_engine::init_headless_audio();
+        Interpreter::new()
+    }
+
+    fn run(interpreter: &mut Interpreter, source: &str) -> String {
+        interpreter.execute_command(source, 0, 0).unwrap_or_else(|e| panic!("{}: {}", source, e))
+    }
+
+    #[test]
+    fn functions_assign_to_outer_variables() {
+        let mut interpreter = interpreter();
+        run(&mut interpreter, "let count = 0\nfunction bump() {\n    count = count + 1\n}\nbump()\nbump()");
+        assert_eq!(run(&mut interpreter, "count"), "2");
+    }
+
+    #[test]
+    fn let_in_a_block_shadows_without_leaking() {
+        let mut interpreter = interpreter();
+        run(&mut interpreter, "let x = 1\nlet inner = 0\n{\n    let x = 2\n    inner = x\n}");
+        assert_eq!(run(&mut interpreter, "inner"), "2");
+        assert_eq!(run(&mut interpreter, "x"), "1");
+    }
+
+    #[test]
+    fn closures_keep_their_scope_after_it_returns() {
+        let mut interpreter = interpreter();
+        run(&mut interpreter, "function make() {\n    let secret = 42\n    function get() {\n        return secret\n    }\n    return get\n}\nlet g = make()");
+        assert_eq!(run(&mut interpreter, "g()"), "42");
+        assert!(interpreter.execute_command("secret", 0, 0).is_err());
+    }
+
+    #[test]
+    fn collision_scripts_see_self_and_ball_in_nested_scopes() {
+        let mut interpreter = interpreter();
+        let square = interpreter.game_objects.create_square(3.0, 3.0);
+        let ball = interpreter.game_objects.create_ball(1.0, 1.0, 0.0, 0.0);
+        interpreter.game_objects.get_square_mut(square).unwrap()
+            .set_script("function note() {\n    who = self.name\n}\n{\n    note()\n}".to_string());
+        run(&mut interpreter, "let who = 0\nlet hitter = 0\non hit {\n    {\n        hitter = ball.name\n    }\n}");
+
+        interpreter.execute_collision_script(ball, square);
+        interpreter.fire_event("hit", Some(ball), Some(square));
+        assert_eq!(run(&mut interpreter, "who"), interpreter.game_objects.get_square_name(square).unwrap());
+        assert_eq!(run(&mut interpreter, "hitter"), interpreter.game_objects.get_ball_name(ball).unwrap());
+        assert_eq!(interpreter.current_script_owner, None);
+        assert_eq!(interpreter.colliding_ball, None);
+    }
+
+    #[test]
+    fn console_one_liners_report_plain_errors() {
+        let mut interpreter = interpreter();
+        let error = interpreter.execute_command("missing", 0, 0).unwrap_err();
+        assert_eq!(error.to_string(), "Undefined variable: missing");
+
+        let error = interpreter.execute_command("let a = 1\nmissing", 0, 0).unwrap_err();
+        assert_eq!(error.to_string(), "line 2, column 1: Undefined variable: missing");
+
+        // Failures inside a function still say where, and how it got there
+        run(&mut interpreter, "function f() {\n    missing\n}");
+        let error = interpreter.execute_command("f()", 0, 0).unwrap_err().to_string();
+        assert!(error.starts_with("line 2, column 5: Undefined variable: missing\n  in f() called at line 1"), "{}", error);
+    }
 }
\ No newline at end of file
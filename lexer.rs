@@ -15,6 +15,9 @@ pub enum TokenType {
     For,
     Function,
     Return,
+    In,         // for "for i in 1..8"
+    And,
+    Or,
     Set,        // for "set direction" command
     Direction,  // for "direction" keyword
     Color,      // New: for "color" keyword
@@ -36,6 +39,9 @@ pub enum TokenType {
     Run,        // New: for "run" command
     Slice,      // New: for "slice" command
     Waveform,   // New: for "waveform" command
+    On,         // for "on wall { ... }" event handlers
+    Fx,         // for "fx ball1 delay 0.25 0.4"
+    MidiRecord, // for "midi_record start" / "midi_record stop \"take.mid\""
     
     // Direction keywords
     Left,
@@ -68,6 +74,7 @@ pub enum TokenType {
     Minus,
     Multiply,
     Divide,
+    Modulo,
     Assign,
     Equal,
     NotEqual,
@@ -86,6 +93,7 @@ pub enum TokenType {
     Comma,
     Semicolon,
     Dot,
+    DotDot,     // Range in for loops: 1..8
     
     // Special
     Newline,
@@ -160,11 +168,18 @@ impl Lexer {
             ']' => Ok(Token::new(TokenType::RightBracket, start_line, start_column)),
             ',' => Ok(Token::new(TokenType::Comma, start_line, start_column)),
             ';' => Ok(Token::new(TokenType::Semicolon, start_line, start_column)),
-            '.' => Ok(Token::new(TokenType::Dot, start_line, start_column)),
+            '.' => {
+                if self.match_char('.') {
+                    Ok(Token::new(TokenType::DotDot, start_line, start_column))
+                } else {
+                    Ok(Token::new(TokenType::Dot, start_line, start_column))
+                }
+            },
             '+' => Ok(Token::new(TokenType::Plus, start_line, start_column)),
             '-' => Ok(Token::new(TokenType::Minus, start_line, start_column)),
             '*' => Ok(Token::new(TokenType::Multiply, start_line, start_column)),
             '/' => Ok(Token::new(TokenType::Divide, start_line, start_column)),
+            '%' => Ok(Token::new(TokenType::Modulo, start_line, start_column)),
             '=' => {
                 if self.match_char('=') {
                     Ok(Token::new(TokenType::Equal, start_line, start_column))
@@ -234,6 +249,14 @@ impl Lexer {
         }
     }
 
+    fn peek_next(&self) -> char {
+        if self.position + 1 >= self.input.len() {
+            '\0'
+        } else {
+            self.input[self.position + 1]
+        }
+    }
+
     fn match_char(&mut self, expected: char) -> bool {
         if self.is_at_end() || self.input[self.position] != expected {
             false
@@ -286,6 +309,10 @@ impl Lexer {
         number_str.push(first_digit);
         
         while !self.is_at_end() && (self.peek().is_ascii_digit() || self.peek() == '.') {
+            // "1..8" is a range, not a malformed number
+            if self.peek() == '.' && self.peek_next() == '.' {
+                break;
+            }
             number_str.push(self.advance());
         }
         
@@ -314,6 +341,9 @@ impl Lexer {
             "for" => TokenType::For,
             "function" => TokenType::Function,
             "return" => TokenType::Return,
+            "in" => TokenType::In,
+            "and" => TokenType::And,
+            "or" => TokenType::Or,
             "set" => TokenType::Set,
             "direction" => TokenType::Direction,
             "color" => TokenType::Color,
@@ -335,6 +365,9 @@ impl Lexer {
             "verbose" => TokenType::Verbose,
             "slice" => TokenType::Slice,
                 "waveform" => TokenType::Waveform,
+            "on" => TokenType::On,
+            "fx" => TokenType::Fx,
+            "midi_record" => TokenType::MidiRecord,
             "left" => TokenType::Left,
             "right" => TokenType::Right,
             "up" => TokenType::Up,
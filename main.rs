@@ -12,10 +12,19 @@ mod ball;
 mod square;
 mod physics_engine;
 mod game_state;
+mod environment;
 mod audio_engine;
+mod mixer;
+mod synth;
+mod effects;
+mod midi;
+mod osc;
+mod transport;
+mod project;
 mod script_editor;
 mod waveform_editor;
 mod input_mapping; // Add this line
+mod headless;
 
 use winit::{
     event::{Event, WindowEvent, KeyboardInput, MouseButton, ElementState},
@@ -60,6 +69,12 @@ fn copy_audio_file_to_samples(source_path: &str) -> Result<String, Box<dyn std::
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     
+    // Run a scene without a window or audio device: cantv3 --headless scene.cant --seconds 30
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--headless") {
+        return headless::run_from_args(&args);
+    }
+    
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("Quadracollision Canticle")
@@ -124,29 +139,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         winit::event::VirtualKeyCode::Escape => {
                                             // Save slice markers before closing waveform editor
                                             if let Some(ref editor) = waveform_editor {
-                                                let slice_markers = editor.get_slice_markers();
-                                                if !slice_markers.is_empty() {
-                                                    if let Some(sample_key) = editor.get_loaded_sample_key() {
-                                                        // Convert sample indices to time in seconds
-                                                        let sample_rate = editor.get_sample_rate();
-                                                        let markers_in_seconds: Vec<f64> = slice_markers.iter()
-                                                            .map(|&sample_index| (sample_index as f64) / (sample_rate as f64))
-                                                            .collect();
-                                                        
-                                                        // Save time-based markers to audio engine
-                                                        match crate::audio_engine::set_sample_markers(sample_key, markers_in_seconds) {
-                                                            Ok(()) => {
-                                                                console.add_output(&format!("Saved {} slice markers for sample: {}", slice_markers.len(), sample_key));
-                                                            },
-                                                            Err(e) => {
-                                                                console.add_output(&format!("Failed to save slice markers: {}", e));
-                                                            }
+                                                if let Some(sample_key) = editor.get_loaded_sample_key() {
+                                                    // Saved even when empty, so removing every marker clears the saved ones
+                                                    let markers_in_seconds = editor.slice_markers_in_seconds();
+                                                    let marker_count = markers_in_seconds.len();
+                                                    
+                                                    // Kept in the engine for this session and in a file next to the sample for the next
+                                                    match crate::audio_engine::set_sample_markers(sample_key, markers_in_seconds)
+                                                        .and_then(|_| crate::audio_engine::save_sample_markers(sample_key)) {
+                                                        Ok(markers_path) => {
+                                                            console.add_output(&format!("Saved {} slice markers for sample: {} ({})", marker_count, sample_key, markers_path));
+                                                        },
+                                                        Err(e) => {
+                                                            console.add_output(&format!("Failed to save slice markers: {}", e));
                                                         }
-                                                    } else {
-                                                        console.add_output("No sample loaded - slice markers not saved");
                                                     }
-                                                } else {
-                                                    console.add_output("No slice markers to save");
+                                                } else if !editor.get_slice_markers().is_empty() {
+                                                    console.add_output("No sample loaded - slice markers not saved");
                                                 }
                                             }
                                             
@@ -155,6 +164,80 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                             console.add_output("Waveform editor closed");
                                             redraw_requested = true;
                                         }
+                                        winit::event::VirtualKeyCode::A => {
+                                            // Auto-slice: markers at every detected hit
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                let count = editor.auto_slice();
+                                                console.add_output(&format!("Auto-slice found {} onsets (sensitivity {:.1}, -/= to change)", count, editor.get_onset_sensitivity()));
+                                            } else {
+                                                console.add_output("No waveform editor available");
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::Minus | winit::event::VirtualKeyCode::Equals => {
+                                            // Sensitivity down/up, then slice again so the change shows right away
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                let delta = if key_code == winit::event::VirtualKeyCode::Minus { -0.1 } else { 0.1 };
+                                                let sensitivity = editor.change_onset_sensitivity(delta);
+                                                let count = editor.auto_slice();
+                                                console.add_output(&format!("Onset sensitivity {:.1}: {} onsets", sensitivity, count));
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::Key0 | winit::event::VirtualKeyCode::Key1
+                                        | winit::event::VirtualKeyCode::Key2 | winit::event::VirtualKeyCode::Key3
+                                        | winit::event::VirtualKeyCode::Key4 | winit::event::VirtualKeyCode::Key5
+                                        | winit::event::VirtualKeyCode::Key6 | winit::event::VirtualKeyCode::Key7
+                                        | winit::event::VirtualKeyCode::Key8 | winit::event::VirtualKeyCode::Key9 => {
+                                            // A number for the next key: 1 6 D slices into 16, 1 2 0 B sets the loop to 120 BPM
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                // Key1..Key9 are followed by Key0, so 10 wraps round to '0'
+                                                let digit = (b'0' + (key_code as u8 - winit::event::VirtualKeyCode::Key1 as u8 + 1) % 10) as char;
+                                                let typed = editor.push_digit(digit);
+                                                console.add_output(&format!("{} (D: slice into, B: loop BPM)", typed));
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::D => {
+                                            // Equal divisions: the number typed before, or 16
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                let divisions = editor.take_number().unwrap_or(crate::waveform_editor::DEFAULT_DIVISIONS);
+                                                let count = editor.slice_into(divisions);
+                                                console.add_output(&format!("Sliced into {} equal parts", count));
+                                            } else {
+                                                console.add_output("No waveform editor available");
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::Z => {
+                                            // Zero-crossing snap, so slices don't click at their edges
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                let on = editor.toggle_zero_crossing_snap();
+                                                console.add_output(&format!("Snap to zero crossings {}", if on { "on" } else { "off" }));
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::G => {
+                                            // Beat grid snap at the loop's tempo
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                let on = editor.toggle_grid_snap();
+                                                console.add_output(&format!("Snap to 1/16 grid at {} BPM {}", editor.get_loop_bpm(), if on { "on" } else { "off" }));
+                                            }
+                                            redraw_requested = true;
+                                        }
+                                        winit::event::VirtualKeyCode::B => {
+                                            // Loop tempo for grid snap: the number typed before
+                                            if let Some(ref mut editor) = waveform_editor {
+                                                match editor.take_number() {
+                                                    Some(bpm) => {
+                                                        editor.set_loop_bpm(bpm as f32);
+                                                        console.add_output(&format!("Loop tempo {} BPM, snapping to the 1/16 grid", bpm));
+                                                    },
+                                                    None => console.add_output(&format!("Loop tempo is {} BPM; type a number then B to change it", editor.get_loop_bpm())),
+                                                }
+                                            }
+                                            redraw_requested = true;
+                                        }
                                         winit::event::VirtualKeyCode::Space => {
                             // Only handle Space when no modifiers are pressed
                             // Let Shift+Space be handled by graphics module for zoom reset
@@ -166,13 +249,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     editor.set_cursor_position(cursor_pos);
                                     
                                     // Check if there's a slice marker at the current cursor position
+                                    // (or where a marker placed there would snap to)
+                                    let snapped_pos = editor.snap_position(cursor_pos);
                                     let slice_markers = editor.get_slice_markers();
                                     let mut marker_found = false;
                                     
                                     // Look for a marker within a small tolerance (e.g., 100 samples)
                                     let tolerance = 100.0;
                                     for (idx, &marker) in slice_markers.iter().enumerate() {
-                                        if (marker - cursor_pos).abs() <= tolerance {
+                                        if (marker - cursor_pos).abs() <= tolerance || marker == snapped_pos {
                                             // Remove the marker
                                             editor.remove_slice_marker(idx);
                                             let message = format!("Slice marker removed at position: {}", marker);
@@ -184,8 +269,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                     
                                     if !marker_found {
                                         // Add slice marker at cursor position
-                                        editor.add_slice_marker();
-                                        let message = format!("Slice marker added at position: {}", cursor_pos);
+                                        let position = editor.add_slice_marker();
+                                        let message = format!("Slice marker added at position: {}", position);
                                         console.add_output(&message);
                                     }
                                     
@@ -348,9 +433,30 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let dt = now.duration_since(last_update).as_secs_f64();
                 last_update = now;
                 
+                // Messages from OSC controllers run like typed commands
+                let osc_output = interpreter.poll_osc();
+                if !osc_output.is_empty() {
+                    for message in &osc_output {
+                        console.add_output(message);
+                    }
+                    if let Some(grid_state) = interpreter.get_grid_state() {
+                        graphics.set_grid_size(grid_state.width, grid_state.height);
+                    }
+                    redraw_requested = true;
+                }
+                
                 // Update physics if game is playing
                 interpreter.update_physics(dt);
                 
+                // Show script errors from collision scripts and run files in the console
+                let script_errors = interpreter.take_console_messages();
+                if !script_errors.is_empty() {
+                    for message in &script_errors {
+                        console.add_error(message);
+                    }
+                    redraw_requested = true;
+                }
+                
                 // Update script editor cursor blink if active
                 if interpreter.is_script_editor_active() {
                     interpreter.update_script_editor_cursor();
@@ -422,9 +528,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                                         match crate::audio_engine::get_sample_markers(sample_key) {
                                             Ok(saved_markers) => {
                                                 if !saved_markers.is_empty() {
-                                                    // Convert f64 markers back to f32 for the waveform editor
-                                                    let markers_f32: Vec<f32> = saved_markers.iter().map(|&x| x as f32).collect();
-                                                    editor.load_slice_markers(markers_f32);
+                                                    editor.load_slice_markers_from_seconds(&saved_markers);
                                                     console.add_output(&format!("Loaded {} previously saved slice markers", saved_markers.len()));
                                                 }
                                             }
@@ -0,0 +1,381 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::transport::Transport;
+
+// Ticks per quarter note in written files
+const TICKS_PER_BEAT: u16 = 480;
+// Collisions have no note-off, so every note lasts a sixteenth (cut short by a repeat of the same note)
+const NOTE_TICKS: u64 = TICKS_PER_BEAT as u64 / 4;
+// Note used when neither the square nor the ball has a midi_note
+pub const DEFAULT_NOTE: u8 = 60;
+// Ball speed (grid cells per second) that records at full velocity
+const FULL_VELOCITY_SPEED: f64 = 10.0;
+// General MIDI plays channel 10 (index 9) as drums, so balls skip it
+const DRUM_CHANNEL: u8 = 9;
+
+// What sets the velocity of recorded notes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocitySource {
+    Volume, // The ball's volume, so the file matches what was heard
+    Speed,  // How fast the ball was moving
+}
+
+impl VelocitySource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "volume" => Some(VelocitySource::Volume),
+            "speed" => Some(VelocitySource::Speed),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VelocitySource::Volume => "volume",
+            VelocitySource::Speed => "speed",
+        }
+    }
+
+    /// MIDI velocity (1 - 127) of a hit by a ball with this speed and volume.
+    pub fn velocity(&self, speed: f64, volume: f32) -> u8 {
+        let level = match self {
+            VelocitySource::Volume => volume as f64,
+            VelocitySource::Speed => speed / FULL_VELOCITY_SPEED,
+        };
+        (level * 127.0).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+/// MIDI channel (0 - 15) for the ball with this sequence number. Balls take
+/// the channels in turn, leaving out the drum channel, and share them past 15.
+pub fn channel_for_ball(sequence_number: u32) -> u8 {
+    let index = (sequence_number.saturating_sub(1) % 15) as u8;
+    if index >= DRUM_CHANNEL { index + 1 } else { index }
+}
+
+#[derive(Debug, Clone)]
+struct RecordedNote {
+    time: f64, // Seconds since the recording started
+    ball: u32,
+    note: u8,
+    velocity: u8,
+}
+
+// One track per ball, even when balls share a channel
+#[derive(Debug, Clone)]
+struct BallTrack {
+    name: String,
+    channel: u8,
+}
+
+/// Collisions captured by `midi_record start`, written as a Standard MIDI File on stop.
+#[derive(Debug, Clone)]
+pub struct MidiRecording {
+    pub velocity_source: VelocitySource,
+    notes: Vec<RecordedNote>,
+    tracks: BTreeMap<u32, BallTrack>, // By ball id, so tracks follow creation order
+    bpm: f64,
+    beats_per_bar: u32,
+    origin: f64, // Simulation time the current run is measured from
+    offset: f64, // Recording time already spent in earlier runs
+}
+
+impl MidiRecording {
+    // Tempo and bar length come from the transport when recording starts
+    pub fn new(simulation_time: f64, transport: &Transport, velocity_source: VelocitySource) -> Self {
+        Self {
+            velocity_source,
+            notes: Vec::new(),
+            tracks: BTreeMap::new(),
+            bpm: transport.bpm,
+            beats_per_bar: transport.beats_per_bar,
+            origin: simulation_time,
+            offset: 0.0,
+        }
+    }
+
+    pub fn note_count(&self) -> usize {
+        self.notes.len()
+    }
+
+    pub fn add_note(&mut self, simulation_time: f64, ball: u32, ball_name: &str, channel: u8, note: u8, velocity: u8) {
+        self.tracks.entry(ball).or_insert_with(|| BallTrack { name: ball_name.to_string(), channel });
+        self.notes.push(RecordedNote {
+            time: self.offset + (simulation_time - self.origin).max(0.0),
+            ball,
+            note,
+            velocity,
+        });
+    }
+
+    // Play was stopped at `simulation_time` and the clock goes back to 0; the
+    // next run carries on after the time recorded so far
+    pub fn end_run(&mut self, simulation_time: f64) {
+        self.offset += (simulation_time - self.origin).max(0.0);
+        self.origin = 0.0;
+    }
+
+    /// Writes the take as a format 1 file: a tempo track, then one track per ball.
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Cannot create directory {}: {}", parent.display(), e))?;
+            }
+        }
+        std::fs::write(path, self.to_bytes())
+            .map_err(|e| format!("Cannot write MIDI file {}: {}", path.display(), e))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut tracks = vec![self.tempo_track()];
+        for (&ball, track) in &self.tracks {
+            tracks.push(self.note_track(ball, track));
+        }
+
+        let mut file = Vec::new();
+        file.extend_from_slice(b"MThd");
+        file.extend_from_slice(&6u32.to_be_bytes());
+        file.extend_from_slice(&1u16.to_be_bytes());
+        file.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        file.extend_from_slice(&TICKS_PER_BEAT.to_be_bytes());
+        for track in tracks {
+            file.extend_from_slice(b"MTrk");
+            file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            file.extend_from_slice(&track);
+        }
+        file
+    }
+
+    fn to_ticks(&self, seconds: f64) -> u64 {
+        (seconds * self.bpm / 60.0 * TICKS_PER_BEAT as f64).round() as u64
+    }
+
+    fn tempo_track(&self) -> Vec<u8> {
+        let microseconds_per_beat = (60_000_000.0 / self.bpm).round() as u32;
+        let mut events = TrackWriter::new();
+        events.meta(0, 0x03, b"cant");
+        events.meta(0, 0x51, &microseconds_per_beat.to_be_bytes()[1..]);
+        // Numerator, denominator as a power of 2, clocks per click, 32nds per quarter
+        events.meta(0, 0x58, &[self.beats_per_bar.min(255) as u8, 2, 24, 8]);
+        events.finish(0)
+    }
+
+    fn note_track(&self, ball: u32, ball_track: &BallTrack) -> Vec<u8> {
+        let notes: Vec<(u64, &RecordedNote)> = self.notes.iter()
+            .filter(|note| note.ball == ball)
+            .map(|note| (self.to_ticks(note.time), note))
+            .collect();
+
+        // (tick, is note-on, note, velocity); offs sort before ons at the same tick
+        let mut events: Vec<(u64, bool, u8, u8)> = Vec::new();
+        for (index, &(start, note)) in notes.iter().enumerate() {
+            let next_same_note = notes[index + 1..].iter()
+                .find(|(_, later)| later.note == note.note)
+                .map(|&(tick, _)| tick);
+            let end = match next_same_note {
+                Some(next) => (start + NOTE_TICKS).min(next),
+                None => start + NOTE_TICKS,
+            };
+            if end <= start {
+                continue; // Same note again on the same tick
+            }
+            events.push((start, true, note.note, note.velocity));
+            events.push((end, false, note.note, 0));
+        }
+        events.sort_by_key(|&(tick, on, note, _)| (tick, on, note));
+
+        let mut track = TrackWriter::new();
+        track.meta(0, 0x03, ball_track.name.as_bytes());
+        let status_channel = ball_track.channel & 0x0F;
+        for &(tick, on, note, velocity) in &events {
+            let status = if on { 0x90 } else { 0x80 } | status_channel;
+            track.event(tick, &[status, note & 0x7F, velocity & 0x7F]);
+        }
+        let last_tick = events.last().map_or(0, |&(tick, ..)| tick);
+        track.finish(last_tick)
+    }
+}
+
+// Builds the body of one MTrk chunk from events at absolute tick times
+struct TrackWriter {
+    bytes: Vec<u8>,
+    tick: u64,
+}
+
+impl TrackWriter {
+    fn new() -> Self {
+        Self { bytes: Vec::new(), tick: 0 }
+    }
+
+    fn event(&mut self, tick: u64, data: &[u8]) {
+        write_variable_length(&mut self.bytes, tick.saturating_sub(self.tick));
+        self.tick = self.tick.max(tick);
+        self.bytes.extend_from_slice(data);
+    }
+
+    fn meta(&mut self, tick: u64, kind: u8, data: &[u8]) {
+        let mut event = vec![0xFF, kind];
+        write_variable_length(&mut event, data.len() as u64);
+        event.extend_from_slice(data);
+        self.event(tick, &event);
+    }
+
+    fn finish(mut self, tick: u64) -> Vec<u8> {
+        self.meta(tick, 0x2F, &[]);
+        self.bytes
+    }
+}
+
+// MIDI variable-length quantity: 7 bits per byte, high bit set on all but the last
+fn write_variable_length(bytes: &mut Vec<u8>, value: u64) {
+    let value = value.min(0x0FFF_FFFF); // Largest value the format allows
+    let mut groups = vec![(value & 0x7F) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7F) as u8 | 0x80);
+        rest >>= 7;
+    }
+    bytes.extend(groups.iter().rev());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable_length(value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_variable_length(&mut bytes, value);
+        bytes
+    }
+
+    // Reads a variable-length quantity back, returning it and the bytes used
+    fn read_variable_length(bytes: &[u8]) -> (u64, usize) {
+        let mut value = 0u64;
+        for (index, &byte) in bytes.iter().enumerate() {
+            value = (value << 7) | (byte & 0x7F) as u64;
+            if byte & 0x80 == 0 {
+                return (value, index + 1);
+            }
+        }
+        panic!("unterminated variable-length quantity");
+    }
+
+    // Splits a file into its chunks, checking every length fits
+    fn chunks(file: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut position = 0;
+        while position < file.len() {
+            let kind = &file[position..position + 4];
+            let length = u32::from_be_bytes(file[position + 4..position + 8].try_into().unwrap()) as usize;
+            chunks.push((kind, &file[position + 8..position + 8 + length]));
+            position += 8 + length;
+        }
+        assert_eq!(position, file.len());
+        chunks
+    }
+
+    // (absolute tick, event bytes) for every event of a track body
+    fn events(track: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        let mut events = Vec::new();
+        let mut position = 0;
+        let mut tick = 0;
+        while position < track.len() {
+            let (delta, used) = read_variable_length(&track[position..]);
+            tick += delta;
+            position += used;
+            let length = if track[position] == 0xFF {
+                let (data_length, used) = read_variable_length(&track[position + 2..]);
+                2 + used + data_length as usize
+            } else {
+                3
+            };
+            events.push((tick, track[position..position + length].to_vec()));
+            position += length;
+        }
+        events
+    }
+
+    #[test]
+    fn variable_length_quantities_round_trip() {
+        assert_eq!(variable_length(0), vec![0x00]);
+        assert_eq!(variable_length(0x7F), vec![0x7F]);
+        assert_eq!(variable_length(0x80), vec![0x81, 0x00]);
+        assert_eq!(variable_length(0x3FFF), vec![0xFF, 0x7F]);
+        assert_eq!(variable_length(0x20_0000), vec![0x81, 0x80, 0x80, 0x00]);
+        assert_eq!(variable_length(0x0FFF_FFFF), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+        // Larger values are capped at the format's maximum
+        assert_eq!(variable_length(u64::MAX), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+        for value in [1, 127, 128, 480, 16_383, 16_384, 1_000_000] {
+            assert_eq!(read_variable_length(&variable_length(value)), (value, variable_length(value).len()));
+        }
+    }
+
+    #[test]
+    fn balls_skip_the_drum_channel() {
+        let channels: Vec<u8> = (1..=16).map(channel_for_ball).collect();
+        assert_eq!(channels, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15, 0]);
+        assert!(!channels.contains(&DRUM_CHANNEL));
+    }
+
+    #[test]
+    fn file_has_a_tempo_track_and_a_track_per_ball() {
+        let mut recording = MidiRecording::new(0.0, &Transport::new(), VelocitySource::Volume);
+        recording.add_note(0.0, 7, "ball1", channel_for_ball(1), 60, 100);
+        recording.add_note(0.5, 7, "ball1", channel_for_ball(1), 62, 90);
+        // ball16 shares ball1's channel but keeps its own track and name
+        recording.add_note(0.25, 22, "ball16", channel_for_ball(16), 64, 80);
+
+        let file = recording.to_bytes();
+        let chunks = chunks(&file);
+        assert_eq!(chunks.len(), 4);
+        let (kind, header) = chunks[0];
+        assert_eq!(kind, b"MThd");
+        assert_eq!(header, &[0, 1, 0, 3, 0x01, 0xE0]); // Format 1, 3 tracks, 480 ticks per beat
+        assert!(chunks[1..].iter().all(|(kind, _)| *kind == b"MTrk"));
+
+        // 120 bpm: 500000 microseconds per beat
+        let tempo = events(chunks[1].1);
+        assert!(tempo.contains(&(0, vec![0xFF, 0x51, 3, 0x07, 0xA1, 0x20])));
+        assert_eq!(tempo.last().unwrap().1, vec![0xFF, 0x2F, 0]);
+
+        let ball1 = events(chunks[2].1);
+        assert_eq!(ball1, vec![
+            (0, [&[0xFF, 0x03, 5][..], b"ball1"].concat()),
+            (0, vec![0x90, 60, 100]),
+            (120, vec![0x80, 60, 0]),
+            (480, vec![0x90, 62, 90]),
+            (600, vec![0x80, 62, 0]),
+            (600, vec![0xFF, 0x2F, 0]),
+        ]);
+
+        let ball16 = events(chunks[3].1);
+        assert_eq!(ball16[0].1, [&[0xFF, 0x03, 6][..], b"ball16"].concat());
+        assert_eq!(ball16[1], (240, vec![0x90, 64, 80]));
+    }
+
+    #[test]
+    fn repeated_notes_are_cut_short_by_the_next_one() {
+        let mut recording = MidiRecording::new(0.0, &Transport::new(), VelocitySource::Volume);
+        recording.add_note(0.0, 1, "ball1", 0, 60, 100);
+        recording.add_note(0.0625, 1, "ball1", 0, 60, 100); // A 32nd later, 60 ticks
+        let file = recording.to_bytes();
+        let ball1 = events(chunks(&file)[2].1);
+        assert_eq!(&ball1[1..5], &[
+            (0, vec![0x90, 60, 100]),
+            (60, vec![0x80, 60, 0]),
+            (60, vec![0x90, 60, 100]),
+            (180, vec![0x80, 60, 0]),
+        ]);
+    }
+
+    #[test]
+    fn later_runs_continue_after_earlier_ones() {
+        let mut recording = MidiRecording::new(2.0, &Transport::new(), VelocitySource::Volume);
+        recording.add_note(2.5, 1, "ball1", 0, 60, 100);
+        recording.end_run(3.0); // One second recorded, then the clock restarts
+        recording.add_note(0.5, 1, "ball1", 0, 60, 100);
+        let times: Vec<f64> = recording.notes.iter().map(|note| note.time).collect();
+        assert_eq!(times, vec![0.5, 1.5]);
+    }
+}
\ No newline at end of file
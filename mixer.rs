@@ -0,0 +1,413 @@
+use rodio::Source;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::effects::{EffectChain, EffectSettings};
+
+// Format of everything the mixer outputs
+pub const MIXER_SAMPLE_RATE: u32 = 44100;
+pub const MIXER_CHANNELS: u16 = 2;
+
+const DEFAULT_POLYPHONY: usize = 32;
+// Frames rendered per lock of the mixer state on the audio thread
+const BLOCK_FRAMES: usize = 256;
+
+// A decoded sound playing from a sample's PCM, resampled to the mixer rate
+pub struct Voice {
+    pcm: Arc<Vec<f32>>, // Interleaved PCM at the sample's own rate
+    channels: usize,
+    step: f64,          // Source frames advanced per output frame
+    start_frame: u64,   // Output frame at which the voice begins
+    position: f64,      // Next (fractional) frame to read from pcm
+    end: usize,         // Frame in pcm at which the voice stops
+    volume: f32,
+    pan: f32,           // -1.0 = left, 0.0 = centre, 1.0 = right
+    bus: Option<u32>,   // Effect bus (a ball id) the voice is mixed through
+}
+
+impl Voice {
+    /// Play frames `start..end` of `pcm` (interleaved, `channels` wide, at `sample_rate`).
+    /// `rate` speeds playback up or down, changing pitch with it (2.0 = an octave up).
+    pub fn new(pcm: Arc<Vec<f32>>, channels: u16, sample_rate: u32, start: usize, end: usize, volume: f32, rate: f64) -> Self {
+        let channels = channels.max(1) as usize;
+        let end = end.min(pcm.len() / channels);
+        Self {
+            pcm,
+            channels,
+            step: sample_rate as f64 / MIXER_SAMPLE_RATE as f64 * rate,
+            start_frame: 0,
+            position: start as f64,
+            end,
+            volume,
+            pan: 0.0,
+            bus: None,
+        }
+    }
+
+    pub fn with_bus(mut self, bus: Option<u32>) -> Self {
+        self.bus = bus;
+        self
+    }
+
+    pub fn with_pan(mut self, pan: f32) -> Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self
+    }
+
+    // Balance law: the side being panned towards stays at full level, so centred
+    // voices sound exactly as before panning existed
+    fn gains(&self) -> (f32, f32) {
+        (self.volume * (1.0 - self.pan).min(1.0), self.volume * (1.0 + self.pan).min(1.0))
+    }
+
+    fn is_finished(&self) -> bool {
+        self.position >= self.end as f64
+    }
+
+    // Stereo value of a source frame; mono is copied to both sides
+    fn frame(&self, index: usize) -> (f32, f32) {
+        let base = index * self.channels;
+        if self.channels == 1 {
+            (self.pcm[base], self.pcm[base])
+        } else {
+            (self.pcm[base], self.pcm[base + 1])
+        }
+    }
+
+    // Linearly interpolated stereo value at the current position
+    fn current(&self) -> (f32, f32) {
+        let index = self.position as usize;
+        let fraction = (self.position - index as f64) as f32;
+        let (l0, r0) = self.frame(index);
+        if fraction == 0.0 || index + 1 >= self.end {
+            return (l0, r0);
+        }
+        let (l1, r1) = self.frame(index + 1);
+        (l0 + (l1 - l0) * fraction, r0 + (r1 - r0) * fraction)
+    }
+
+    // Add this voice's share of the block starting at output frame `block_start` into `out`
+    fn mix_into(&mut self, out: &mut [f32], block_start: u64) {
+        let channels = MIXER_CHANNELS as usize;
+        let frames = out.len() / channels;
+        if self.start_frame >= block_start + frames as u64 {
+            return; // Not due yet
+        }
+
+        // Late voices start at the beginning of the block
+        let offset = self.start_frame.saturating_sub(block_start) as usize;
+        let (left_gain, right_gain) = self.gains();
+        for frame in offset..frames {
+            if self.is_finished() {
+                break;
+            }
+            let (left, right) = self.current();
+            out[frame * channels] += left * left_gain;
+            out[frame * channels + 1] += right * right_gain;
+            self.position += self.step;
+        }
+    }
+}
+
+pub struct MixerState {
+    voices: Vec<Voice>,
+    frame: u64, // Output frames rendered so far
+    polyphony: usize,
+    buses: HashMap<u32, EffectChain>, // Per-ball effects, by ball id
+    master: EffectChain,
+    bus_buffer: Vec<f32>, // Scratch block for mixing one bus
+}
+
+impl MixerState {
+    pub fn new() -> Self {
+        Self {
+            voices: Vec::new(),
+            frame: 0,
+            polyphony: DEFAULT_POLYPHONY,
+            buses: HashMap::new(),
+            master: EffectChain::new(MIXER_SAMPLE_RATE),
+            bus_buffer: Vec::new(),
+        }
+    }
+
+    /// Set the effects of a ball's bus. An empty list removes the bus, cutting off any tail.
+    pub fn set_bus_effects(&mut self, bus: u32, settings: &[EffectSettings]) {
+        if settings.is_empty() {
+            self.buses.remove(&bus);
+        } else {
+            self.buses.entry(bus)
+                .or_insert_with(|| EffectChain::new(MIXER_SAMPLE_RATE))
+                .update(settings);
+        }
+    }
+
+    pub fn set_master_effects(&mut self, settings: &[EffectSettings]) {
+        self.master.update(settings);
+    }
+
+    /// Drop every ball's bus, e.g. when the scene is cleared. Voices still routed
+    /// to one go straight to the output.
+    pub fn clear_buses(&mut self) {
+        self.buses.clear();
+    }
+
+    pub fn current_frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn polyphony(&self) -> usize {
+        self.polyphony
+    }
+
+    pub fn set_polyphony(&mut self, polyphony: usize) {
+        self.polyphony = polyphony.max(1);
+        while self.voices.len() > self.polyphony {
+            self.steal_oldest_voice();
+        }
+    }
+
+    /// Schedule a voice to start at output frame `start_frame`.
+    /// When the polyphony cap is reached the oldest voice is dropped to make room.
+    pub fn schedule(&mut self, mut voice: Voice, start_frame: u64) {
+        if voice.is_finished() {
+            return;
+        }
+
+        while self.voices.len() >= self.polyphony {
+            self.steal_oldest_voice();
+        }
+
+        voice.start_frame = start_frame;
+        self.voices.push(voice);
+    }
+
+    fn steal_oldest_voice(&mut self) {
+        if let Some(oldest) = self.voices.iter().enumerate()
+            .min_by_key(|(_, voice)| voice.start_frame)
+            .map(|(index, _)| index) {
+            self.voices.remove(oldest);
+        }
+    }
+
+    /// Mix the next `out.len() / 2` frames into `out`, replacing its contents.
+    pub fn render(&mut self, out: &mut [f32]) {
+        let channels = MIXER_CHANNELS as usize;
+        let frames = out.len() / channels;
+        let block_start = self.frame;
+        out.iter_mut().for_each(|sample| *sample = 0.0);
+
+        // Voices without effects go straight to the output
+        let buses = &mut self.buses;
+        for voice in &mut self.voices {
+            if !voice.bus.map_or(false, |bus| buses.contains_key(&bus)) {
+                voice.mix_into(out, block_start);
+            }
+        }
+
+        // Every bus runs each block, even without voices, so delay and reverb tails ring out
+        self.bus_buffer.resize(out.len(), 0.0);
+        for (&bus, chain) in buses.iter_mut() {
+            self.bus_buffer.iter_mut().for_each(|sample| *sample = 0.0);
+            for voice in self.voices.iter_mut().filter(|voice| voice.bus == Some(bus)) {
+                voice.mix_into(&mut self.bus_buffer, block_start);
+            }
+            chain.process(&mut self.bus_buffer);
+            for (sample, bus_sample) in out.iter_mut().zip(&self.bus_buffer) {
+                *sample += bus_sample;
+            }
+        }
+
+        self.master.process(out);
+
+        self.voices.retain(|voice| !voice.is_finished());
+        self.frame += frames as u64;
+    }
+}
+
+// Shared handle to the mixer that runs on the output stream
+#[derive(Clone)]
+pub struct Mixer {
+    state: Arc<Mutex<MixerState>>,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MixerState::new())),
+        }
+    }
+
+    // The rodio source to hand to the output stream. It never ends.
+    pub fn source(&self) -> MixerSource {
+        MixerSource {
+            state: Arc::clone(&self.state),
+            buffer: vec![0.0; BLOCK_FRAMES * MIXER_CHANNELS as usize],
+            index: BLOCK_FRAMES * MIXER_CHANNELS as usize,
+        }
+    }
+
+    pub fn current_frame(&self) -> u64 {
+        self.state.lock().map(|state| state.current_frame()).unwrap_or(0)
+    }
+
+    pub fn schedule(&self, voice: Voice, start_frame: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.schedule(voice, start_frame);
+        }
+    }
+
+    pub fn set_polyphony(&self, polyphony: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.set_polyphony(polyphony);
+        }
+    }
+
+    pub fn set_bus_effects(&self, bus: u32, settings: &[EffectSettings]) {
+        if let Ok(mut state) = self.state.lock() {
+            state.set_bus_effects(bus, settings);
+        }
+    }
+
+    pub fn set_master_effects(&self, settings: &[EffectSettings]) {
+        if let Ok(mut state) = self.state.lock() {
+            state.set_master_effects(settings);
+        }
+    }
+
+    pub fn clear_buses(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.clear_buses();
+        }
+    }
+}
+
+pub struct MixerSource {
+    state: Arc<Mutex<MixerState>>,
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl Iterator for MixerSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.index >= self.buffer.len() {
+            match self.state.lock() {
+                Ok(mut state) => state.render(&mut self.buffer),
+                Err(_) => self.buffer.iter_mut().for_each(|sample| *sample = 0.0),
+            }
+            self.index = 0;
+        }
+
+        let sample = self.buffer[self.index];
+        self.index += 1;
+        Some(sample)
+    }
+}
+
+impl Source for MixerSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        MIXER_CHANNELS
+    }
+
+    fn sample_rate(&self) -> u32 {
+        MIXER_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A mono voice of the given frames at the mixer rate
+    fn voice(frames: Vec<f32>) -> Voice {
+        let len = frames.len();
+        Voice::new(Arc::new(frames), 1, MIXER_SAMPLE_RATE, 0, len, 1.0, 1.0)
+    }
+
+    fn render_frames(state: &mut MixerState, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * MIXER_CHANNELS as usize];
+        state.render(&mut out);
+        out
+    }
+
+    #[test]
+    fn voices_on_a_bus_go_through_its_effects() {
+        // Half a second of delay at full mix and no feedback: one echo 22050 frames later
+        let echo = [EffectSettings::Delay { time: 0.5, feedback: 0.0, mix: 1.0 }];
+        let mut state = MixerState::new();
+        state.set_bus_effects(7, &echo);
+        state.schedule(voice(vec![1.0]).with_bus(Some(7)), 0);
+        state.schedule(voice(vec![0.5]).with_bus(Some(8)), 0); // Bus without effects
+        let out = render_frames(&mut state, 22100);
+        assert_eq!(out[0], 1.5);
+        assert_eq!(out[22050 * 2], 1.0);
+        assert_eq!(out[22050 * 2 + 1], 1.0);
+
+        // Once the buses are cleared the same voice is dry
+        let mut state = MixerState::new();
+        state.set_bus_effects(7, &echo);
+        state.clear_buses();
+        state.schedule(voice(vec![1.0]).with_bus(Some(7)), 0);
+        let out = render_frames(&mut state, 22100);
+        assert_eq!(out[0], 1.0);
+        assert_eq!(out[22050 * 2], 0.0);
+    }
+    #[test]
+    fn voices_start_on_their_scheduled_frame() {
+        let mut state = MixerState::new();
+        state.schedule(voice(vec![1.0; 4]), BLOCK_FRAMES as u64 + 44);
+        assert!(render_frames(&mut state, BLOCK_FRAMES).iter().all(|&sample| sample == 0.0));
+
+        let out = render_frames(&mut state, BLOCK_FRAMES);
+        assert_eq!(out[43 * 2], 0.0);
+        assert_eq!(out[44 * 2], 1.0);
+        assert_eq!(out[44 * 2 + 1], 1.0);
+        assert_eq!(out[47 * 2], 1.0);
+        assert_eq!(out[48 * 2], 0.0);
+    }
+
+    #[test]
+    fn late_voices_start_at_the_block_start() {
+        let mut state = MixerState::new();
+        render_frames(&mut state, BLOCK_FRAMES);
+        state.schedule(voice(vec![1.0; 4]), 10); // Already rendered
+        let out = render_frames(&mut state, BLOCK_FRAMES);
+        assert_eq!(out[0], 1.0);
+        assert_eq!(out[3 * 2], 1.0);
+        assert_eq!(out[4 * 2], 0.0);
+    }
+
+    #[test]
+    fn polyphony_steals_the_oldest_voice() {
+        let mut state = MixerState::new();
+        state.set_polyphony(2);
+        state.schedule(voice(vec![1.0; 8]), 0);
+        state.schedule(voice(vec![2.0; 8]), 1);
+        state.schedule(voice(vec![4.0; 8]), 2); // Replaces the voice at frame 0
+        let out = render_frames(&mut state, 4);
+        assert_eq!(out[0], 0.0);
+        assert_eq!(out[2], 2.0);
+        assert_eq!(out[3 * 2], 6.0);
+
+        // Lowering the cap drops the oldest voices still playing
+        state.set_polyphony(1);
+        let out = render_frames(&mut state, 4);
+        assert_eq!(out[0], 4.0);
+    }
+
+    #[test]
+    fn centred_voices_keep_unity_gain() {
+        assert_eq!(voice(vec![1.0]).with_pan(0.0).gains(), (1.0, 1.0));
+        assert_eq!(voice(vec![1.0]).with_pan(1.0).gains(), (0.0, 1.0));
+        assert_eq!(voice(vec![1.0]).with_pan(-0.5).gains(), (1.0, 0.5));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,396 @@
+use std::net::{SocketAddr, UdpSocket};
+
+// Only local tools may drive the interpreter, since /cant/cmd runs any command
+const LISTEN_HOST: &str = "127.0.0.1";
+// Largest datagram read in one go
+const MAX_PACKET_SIZE: usize = 8192;
+// Deepest bundle nesting accepted
+const MAX_BUNDLE_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    String(String),
+}
+
+impl OscArg {
+    pub fn to_text(&self) -> String {
+        match self {
+            OscArg::Int(value) => value.to_string(),
+            OscArg::Float(value) => value.to_string(),
+            OscArg::String(text) => text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+impl OscMessage {
+    pub fn new(address: &str, args: Vec<OscArg>) -> Self {
+        Self { address: address.to_string(), args }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.args.iter().map(|arg| match arg {
+                OscArg::Int(_) => 'i',
+                OscArg::Float(_) => 'f',
+                OscArg::String(_) => 's',
+            }))
+            .collect();
+        write_string(&mut bytes, &tags);
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                OscArg::Float(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+                OscArg::String(text) => write_string(&mut bytes, text),
+            }
+        }
+        bytes
+    }
+
+    /// Messages in one packet: a single message, or every message of a bundle
+    /// (time tags are ignored, everything runs on arrival).
+    pub fn decode_packet(packet: &[u8]) -> Result<Vec<OscMessage>, String> {
+        let mut messages = Vec::new();
+        decode_into(packet, 0, &mut messages)?;
+        Ok(messages)
+    }
+}
+
+fn decode_into(packet: &[u8], depth: usize, messages: &mut Vec<OscMessage>) -> Result<(), String> {
+    if !packet.starts_with(b"#bundle\0") {
+        messages.push(decode_message(packet)?);
+        return Ok(());
+    }
+    if depth >= MAX_BUNDLE_DEPTH {
+        return Err("OSC bundles nested too deeply".to_string());
+    }
+
+    let mut reader = Reader { bytes: packet, position: 16 }; // "#bundle\0" and the time tag
+    if packet.len() < 16 {
+        return Err("OSC bundle is missing its time tag".to_string());
+    }
+    while reader.position < packet.len() {
+        let size = reader.int()?;
+        if size < 0 {
+            return Err("OSC bundle element has a negative size".to_string());
+        }
+        let element = reader.take(size as usize)?;
+        decode_into(element, depth + 1, messages)?;
+    }
+    Ok(())
+}
+
+fn decode_message(packet: &[u8]) -> Result<OscMessage, String> {
+    let mut reader = Reader { bytes: packet, position: 0 };
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        return Err(format!("OSC address must start with '/', got '{}'", address));
+    }
+
+    // Some senders leave out the type tags when there are no arguments
+    if reader.position >= packet.len() {
+        return Ok(OscMessage { address, args: Vec::new() });
+    }
+    let tags = reader.string()?;
+    let tags = tags.strip_prefix(',')
+        .ok_or_else(|| format!("OSC type tags for {} must start with ','", address))?;
+
+    let mut args = Vec::new();
+    for tag in tags.chars() {
+        args.push(match tag {
+            'i' => OscArg::Int(reader.int()?),
+            'f' => OscArg::Float(f32::from_bits(reader.int()? as u32)),
+            's' => OscArg::String(reader.string()?),
+            'T' => OscArg::Int(1),
+            'F' => OscArg::Int(0),
+            _ => return Err(format!("Unsupported OSC argument type '{}' in {}", tag, address)),
+        });
+    }
+    Ok(OscMessage { address, args })
+}
+
+// Reads big-endian values and padded strings from a packet
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.position.checked_add(length)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| "OSC packet is truncated".to_string())?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn int(&mut self) -> Result<i32, String> {
+        let bytes = self.take(4)?;
+        Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Null-terminated, padded with nulls to a multiple of 4 bytes
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.bytes[self.position.min(self.bytes.len())..];
+        let length = rest.iter().position(|&byte| byte == 0)
+            .ok_or_else(|| "OSC string is not terminated".to_string())?;
+        let text = String::from_utf8_lossy(&rest[..length]).to_string();
+        self.take((length + 4) & !3)?;
+        Ok(text)
+    }
+}
+
+fn write_string(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend_from_slice(text.as_bytes());
+    let padding = 4 - text.len() % 4; // Always at least one null
+    bytes.extend(std::iter::repeat_n(0, padding));
+}
+
+/// Resolve a "host:port" target for collision messages. The socket is bound to
+/// LISTEN_HOST, so only IPv4 loopback addresses can be reached from it.
+pub fn local_target(address: &str) -> Result<SocketAddr, String> {
+    use std::net::ToSocketAddrs;
+    let resolved: Vec<SocketAddr> = address.to_socket_addrs()
+        .map_err(|e| format!("Invalid OSC target '{}': {}", address, e))?
+        .collect();
+    resolved.iter()
+        .find(|target| target.is_ipv4() && target.ip().is_loopback())
+        .copied()
+        .ok_or_else(|| format!("OSC target '{}' must be on this machine, e.g. 127.0.0.1:9000", address))
+}
+
+/// UDP endpoint that receives control messages and sends collision messages.
+pub struct OscServer {
+    socket: UdpSocket,
+    port: u16,
+    target: Option<SocketAddr>, // Where collisions go; None sends to whoever last sent us something
+    last_sender: Option<SocketAddr>,
+}
+
+impl OscServer {
+    pub fn bind(port: u16, target: Option<SocketAddr>) -> Result<Self, String> {
+        let socket = UdpSocket::bind((LISTEN_HOST, port))
+            .map_err(|e| format!("Cannot listen for OSC on port {}: {}", port, e))?;
+        socket.set_nonblocking(true)
+            .map_err(|e| format!("Cannot set up OSC socket: {}", e))?;
+        let port = socket.local_addr().map(|address| address.port()).unwrap_or(port);
+        Ok(Self { socket, port, target, last_sender: None })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Every message that has arrived since the last poll. Packets that can't be
+    /// decoded come back as errors so the console can show them.
+    pub fn poll(&mut self) -> Vec<Result<OscMessage, String>> {
+        let mut received = Vec::new();
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, sender)) => {
+                    self.last_sender = Some(sender);
+                    match OscMessage::decode_packet(&buffer[..length]) {
+                        Ok(messages) => received.extend(messages.into_iter().map(Ok)),
+                        Err(e) => received.push(Err(e)),
+                    }
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                // On some platforms an unreachable target shows up here as a failed receive
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => continue,
+                Err(e) => {
+                    received.push(Err(format!("OSC receive failed: {}", e)));
+                    break;
+                },
+            }
+        }
+        received
+    }
+
+    // Fire and forget: a controller that isn't listening shouldn't stop the show
+    pub fn send(&self, message: &OscMessage) {
+        if let Some(target) = self.target.or(self.last_sender) {
+            if let Err(e) = self.socket.send_to(&message.encode(), target) {
+                log::warn!("OSC send to {} failed: {}", target, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_one(packet: &[u8]) -> OscMessage {
+        let mut messages = OscMessage::decode_packet(packet).expect("decodes");
+        assert_eq!(messages.len(), 1);
+        messages.remove(0)
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = b"#bundle\0".to_vec();
+        packet.extend_from_slice(&1u64.to_be_bytes()); // "Immediately"
+        for element in elements {
+            packet.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            packet.extend_from_slice(element);
+        }
+        packet
+    }
+
+    #[test]
+    fn strings_are_null_terminated_and_padded_to_four_bytes() {
+        for (text, padded_length) in [("", 4), ("abc", 4), ("abcd", 8), ("/cant/cmd", 12)] {
+            let mut bytes = Vec::new();
+            write_string(&mut bytes, text);
+            assert_eq!(bytes.len(), padded_length, "{:?}", text);
+            assert!(bytes[text.len()..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let message = OscMessage::new("/cant/collision", vec![
+            OscArg::String("ball1".to_string()),
+            OscArg::String("square12".to_string()),
+            OscArg::Int(-3),
+            OscArg::Float(0.25),
+        ]);
+        let packet = message.encode();
+        assert_eq!(packet.len() % 4, 0);
+        assert_eq!(decode_one(&packet), message);
+
+        let empty = OscMessage::new("/cant/play", Vec::new());
+        assert_eq!(decode_one(&empty.encode()), empty);
+    }
+
+    #[test]
+    fn known_packet_decodes() {
+        // "/cant/cmd" ",s" "play" from the OSC 1.0 layout
+        let packet = b"/cant/cmd\0\0\0,s\0\0play\0\0\0\0";
+        assert_eq!(decode_one(packet), OscMessage::new("/cant/cmd", vec![OscArg::String("play".to_string())]));
+    }
+
+    #[test]
+    fn missing_type_tags_mean_no_arguments() {
+        assert_eq!(decode_one(b"/cant/stop\0\0").args, Vec::new());
+    }
+
+    #[test]
+    fn true_and_false_tags_take_no_bytes() {
+        let packet = b"/x\0\0,TiF\0\0\0\0\0\0\0\x07";
+        assert_eq!(decode_one(packet).args, vec![OscArg::Int(1), OscArg::Int(7), OscArg::Int(0)]);
+    }
+
+    #[test]
+    fn bundles_yield_every_message_in_order() {
+        let first = OscMessage::new("/cant/pause", Vec::new());
+        let second = OscMessage::new("/cant/cmd", vec![OscArg::String("play".to_string())]);
+        let nested = bundle(&[second.encode()]);
+        let messages = OscMessage::decode_packet(&bundle(&[first.encode(), nested])).expect("decodes");
+        assert_eq!(messages, vec![first, second]);
+
+        // A bundle with nothing in it is fine
+        assert_eq!(OscMessage::decode_packet(&bundle(&[])).expect("decodes"), Vec::new());
+    }
+
+    #[test]
+    fn deeply_nested_bundles_are_rejected() {
+        let mut packet = OscMessage::new("/cant/play", Vec::new()).encode();
+        for _ in 0..=MAX_BUNDLE_DEPTH {
+            packet = bundle(&[packet]);
+        }
+        assert!(OscMessage::decode_packet(&packet).is_err());
+    }
+
+    #[test]
+    fn truncated_packets_are_errors_not_panics() {
+        let message = OscMessage::new("/cant/collision", vec![
+            OscArg::String("ball1".to_string()),
+            OscArg::Int(4),
+            OscArg::Float(1.5),
+        ]);
+        let encoded = message.encode();
+        let element = 4 + encoded.len();
+        // Cuts that still leave a valid packet: right after the address (no type tags),
+        // and between bundle elements
+        let packets = [(encoded.clone(), vec![16]), (bundle(&[encoded.clone(), encoded]), vec![16, 16 + element])];
+        for (packet, whole) in &packets {
+            for length in 0..packet.len() {
+                let result = OscMessage::decode_packet(&packet[..length]);
+                assert_eq!(result.is_ok(), whole.contains(&length), "length {}", length);
+            }
+        }
+    }
+
+    #[test]
+    fn badly_padded_or_malformed_packets_are_errors() {
+        let cases: [&[u8]; 8] = [
+            b"",                           // Nothing at all
+            b"/cant/play",                 // Address never terminated
+            b"/cant/cmd\0,s\0\0play\0\0\0\0", // Address padding missing
+            b"/cant/cmd\0\0\0,s\0\0play\0", // Argument padding cut short
+            b"cant\0\0\0\0",               // Address without a leading slash
+            b"/x\0\0s\0\0\0",              // Type tags without the comma
+            b"/x\0\0,b\0\0\0\0\0\0",       // Blobs aren't supported
+            b"/x\0\0,i\0\0\0\0\0",         // Int with only three bytes
+        ];
+        for packet in cases {
+            assert!(OscMessage::decode_packet(packet).is_err(), "{:?}", String::from_utf8_lossy(packet));
+        }
+
+        // Bundle elements must fit inside the bundle and have a sensible size
+        let mut oversized = bundle(&[]);
+        oversized.extend_from_slice(&64i32.to_be_bytes());
+        oversized.extend_from_slice(b"/x\0\0");
+        assert!(OscMessage::decode_packet(&oversized).is_err());
+        let mut negative = bundle(&[]);
+        negative.extend_from_slice(&(-4i32).to_be_bytes());
+        assert!(OscMessage::decode_packet(&negative).is_err());
+        assert!(OscMessage::decode_packet(b"#bundle\0\0\0\0").is_err()); // Time tag cut short
+    }
+    #[test]
+    fn only_loopback_targets_are_accepted() {
+        assert_eq!(local_target("127.0.0.1:9000").unwrap(), "127.0.0.1:9000".parse().unwrap());
+        assert!(local_target("192.0.2.1:9000").is_err());
+        assert!(local_target("[::1]:9000").is_err()); // The socket speaks IPv4 only
+        assert!(local_target("no port").is_err());
+    }
+
+    #[test]
+    fn server_receives_commands_and_sends_to_its_target() {
+        let target = UdpSocket::bind("127.0.0.1:0").unwrap();
+        target.set_read_timeout(Some(std::time::Duration::from_secs(2))).unwrap();
+        let mut server = OscServer::bind(0, Some(target.local_addr().unwrap())).unwrap();
+        assert_ne!(server.port(), 0);
+
+        let command = OscMessage::new("/cant/cmd", vec![OscArg::String("play".to_string())]);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.send_to(&command.encode(), ("127.0.0.1", server.port())).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            received.extend(server.poll());
+            if !received.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(received, vec![Ok(command)]);
+
+        let collision = OscMessage::new("/cant/collision", vec![OscArg::String("ball1".to_string())]);
+        server.send(&collision);
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        let (length, sender) = target.recv_from(&mut buffer).unwrap();
+        assert_eq!(sender.port(), server.port());
+        assert_eq!(decode_one(&buffer[..length]), collision);
+    }
+}
\ No newline at end of file
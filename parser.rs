@@ -1,5 +1,5 @@
 use crate::lexer::{Token, TokenType};
-use crate::ast::{Expr, Stmt, BinaryOp, UnaryOp, DirectionValue, ColorValue, SpeedModification, Program};
+use crate::ast::{Expr, Stmt, BinaryOp, LogicalOp, UnaryOp, DirectionValue, ColorValue, SpeedModification, Program, Span};
 use std::fmt;
 
 pub struct Parser {
@@ -32,12 +32,20 @@ impl Parser {
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let span = self.span();
+        let stmt = self.statement_kind()?;
+        Ok(Stmt::Spanned { span, stmt: Box::new(stmt) })
+    }
+
+    fn statement_kind(&mut self) -> Result<Stmt, ParseError> {
         if self.match_token(&TokenType::Let) {
             self.let_statement()
         } else if self.match_token(&TokenType::If) {
             self.if_statement()
         } else if self.match_token(&TokenType::While) {
             self.while_statement()
+        } else if self.match_token(&TokenType::For) {
+            self.for_statement()
         } else if self.match_token(&TokenType::Function) {
             self.function_statement()
         } else if self.match_token(&TokenType::Return) {
@@ -68,6 +76,14 @@ impl Parser {
             self.slice_statement()
         } else if self.match_token(&TokenType::Waveform) {
             self.waveform_statement()
+        } else if self.match_token(&TokenType::LeftBrace) {
+            Ok(Stmt::Block(self.block()?))
+        } else if self.match_token(&TokenType::On) {
+            self.on_statement()
+        } else if self.match_token(&TokenType::Fx) {
+            self.fx_statement()
+        } else if self.match_token(&TokenType::MidiRecord) {
+            self.midi_record_statement()
         } else {
             self.expression_statement()
         }
@@ -251,14 +267,47 @@ impl Parser {
             
             self.consume_newline_or_semicolon()?;
             Ok(Stmt::SetSpeed { object_name, speed })
+        } else if let Some(property) = self.sound_property() {
+            self.advance(); // consume 'pitch' / 'rate' / 'pan'
+            
+            let object_name = match &self.peek().token_type {
+                TokenType::Identifier(name) => {
+                    let name = name.clone();
+                    self.advance();
+                    name
+                },
+                TokenType::Cursor => {
+                    self.advance();
+                    "cursor".to_string()
+                },
+                TokenType::Self_ => {
+                    self.advance();
+                    "self".to_string()
+                },
+                _ => return Err(ParseError::ExpectedIdentifier(self.peek().line, self.peek().column)),
+            };
+            
+            // Any expression, so scripts can pick the value per hit: set pitch self notes[self.hits % 4]
+            let value = self.expression()?;
+            
+            self.consume_newline_or_semicolon()?;
+            Ok(Stmt::SetProperty { object_name, property, value })
         } else {
             Err(ParseError::Expected {
-                expected: "'direction', 'color', or 'speed'".to_string(),
+                expected: "'direction', 'color', 'speed', 'pitch', 'rate' or 'pan'".to_string(),
                 found: self.peek().clone(),
-                message: "Expected 'direction', 'color', or 'speed' after 'set'".to_string(),
+                message: "Expected 'direction', 'color', 'speed', 'pitch', 'rate' or 'pan' after 'set'".to_string(),
             })
         }
     }
+    
+    // Sound settings that `set` writes straight to the ball property of the same name
+    fn sound_property(&self) -> Option<String> {
+        match &self.peek().token_type {
+            TokenType::Identifier(name) if name == "pitch" || name == "rate" || name == "pan" => Some(name.clone()),
+            _ => None,
+        }
+    }
 
     fn let_statement(&mut self) -> Result<Stmt, ParseError> {
         let name = if let TokenType::Identifier(name) = &self.peek().token_type {
@@ -303,6 +352,130 @@ impl Parser {
         Ok(Stmt::While { condition, body })
     }
 
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let variable = if let TokenType::Identifier(name) = &self.peek().token_type {
+            let name = name.clone();
+            self.advance();
+            name
+        } else {
+            return Err(ParseError::ExpectedIdentifier(self.peek().line, self.peek().column));
+        };
+        
+        self.consume(&TokenType::In, "Expected 'in' after for loop variable")?;
+        
+        // A range or any expression that evaluates to a list
+        let iterable = self.expression()?;
+        
+        // Braced body, or statements up to the next block initiator like if
+        let body = if self.match_token(&TokenType::LeftBrace) {
+            Box::new(Stmt::Block(self.block()?))
+        } else {
+            Box::new(Stmt::Block(self.parse_implicit_block()?))
+        };
+        
+        Ok(Stmt::For { variable, iterable, body })
+    }
+
+    fn on_statement(&mut self) -> Result<Stmt, ParseError> {
+        let event = match &self.peek().token_type {
+            TokenType::Identifier(name) if matches!(name.as_str(), "wall" | "tick" | "hit") => name.clone(),
+            TokenType::Play => "play".to_string(),
+            TokenType::Stop => "stop".to_string(),
+            _ => return Err(ParseError::Expected {
+                expected: "wall, tick, play, stop or hit".to_string(),
+                found: self.peek().clone(),
+                message: "Unknown event after 'on'".to_string(),
+            }),
+        };
+        self.advance();
+        
+        // on hit ball2 { ... } only fires for collisions involving ball2
+        let mut target = None;
+        if event == "hit" {
+            if let TokenType::Identifier(name) = &self.peek().token_type {
+                target = Some(name.clone());
+                self.advance();
+            }
+        }
+        
+        let body = if self.match_token(&TokenType::LeftBrace) {
+            Box::new(Stmt::Block(self.block()?))
+        } else {
+            Box::new(Stmt::Block(self.parse_implicit_block()?))
+        };
+        
+        Ok(Stmt::On { event, target, body })
+    }
+
+    fn fx_statement(&mut self) -> Result<Stmt, ParseError> {
+        let target = match &self.peek().token_type {
+            TokenType::Identifier(name) => name.clone(),
+            TokenType::Self_ => "self".to_string(),
+            TokenType::Cursor => "cursor".to_string(),
+            _ => return Err(ParseError::Expected {
+                expected: "ball name or 'master'".to_string(),
+                found: self.peek().clone(),
+                message: "Expected a ball or 'master' after 'fx'".to_string(),
+            }),
+        };
+        self.advance();
+        
+        let effect = match &self.peek().token_type {
+            TokenType::Identifier(name) => name.clone(),
+            _ => return Err(ParseError::Expected {
+                expected: "lowpass, highpass, delay, reverb or off".to_string(),
+                found: self.peek().clone(),
+                message: "Expected an effect name".to_string(),
+            }),
+        };
+        self.advance();
+        
+        let remove = matches!(&self.peek().token_type, TokenType::Identifier(name) if name == "off");
+        if remove {
+            self.advance();
+        }
+        
+        // Effect parameters, e.g. delay time, feedback and mix
+        let mut arguments = Vec::new();
+        while !remove && !self.check(&TokenType::Newline) && !self.check(&TokenType::Semicolon)
+            && !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            arguments.push(self.spaced_operand()?);
+        }
+        
+        self.consume_newline_or_semicolon()?;
+        Ok(Stmt::Fx { target, effect, arguments, remove })
+    }
+
+    fn midi_record_statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.match_token(&TokenType::Stop) {
+            let path = self.expression()?;
+            self.consume_newline_or_semicolon()?;
+            return Ok(Stmt::MidiRecord { start: false, velocity: None, path: Some(path) });
+        }
+        
+        match &self.peek().token_type {
+            TokenType::Identifier(name) if name == "start" => self.advance(),
+            _ => return Err(ParseError::Expected {
+                expected: "start or stop".to_string(),
+                found: self.peek().clone(),
+                message: "Expected 'start' or 'stop' after 'midi_record'".to_string(),
+            }),
+        };
+        
+        // midi_record start speed: velocity from ball speed instead of volume
+        let velocity = match &self.peek().token_type {
+            TokenType::Identifier(name) => Some(name.clone()),
+            TokenType::Speed => Some("speed".to_string()),
+            _ => None,
+        };
+        if velocity.is_some() {
+            self.advance();
+        }
+        
+        self.consume_newline_or_semicolon()?;
+        Ok(Stmt::MidiRecord { start: true, velocity, path: None })
+    }
+
     fn script_statement(&mut self) -> Result<Stmt, ParseError> {
         // Parse object name in parentheses: script(object_name)
         self.consume(&TokenType::LeftParen, "Expected '(' after 'script'")?;
@@ -389,7 +562,14 @@ impl Parser {
         }
         
         self.consume(&TokenType::RightParen, "Expected ')' after parameters")?;
-        self.consume_newline_or_semicolon()?; // Instead of expecting '{'
+        
+        // A braced body ends at its '}', so functions can be nested and closed over
+        if self.match_token(&TokenType::LeftBrace) {
+            let body = Box::new(Stmt::Block(self.block()?));
+            return Ok(Stmt::Function { name, parameters, body });
+        }
+        
+        self.consume_newline_or_semicolon()?;
         
         // Parse statements until next function/if or EOF
         let body = Box::new(Stmt::Block(self.parse_implicit_block()?));
@@ -434,7 +614,7 @@ impl Parser {
     }
 
     fn assignment(&mut self) -> Result<Expr, ParseError> {
-        let expr = self.or()?;
+        let expr = self.range()?;
         
         if self.match_token(&TokenType::Assign) {
             let value = self.assignment()?;
@@ -444,18 +624,68 @@ impl Parser {
                     value: Box::new(value),
                 });
             }
+            if let Expr::Index { object, index } = expr {
+                return Ok(Expr::IndexAssign {
+                    object,
+                    index,
+                    value: Box::new(value),
+                });
+            }
+            if let Expr::Get { object, property } = expr {
+                return Ok(Expr::Set {
+                    object,
+                    property,
+                    value: Box::new(value),
+                });
+            }
             return Err(ParseError::InvalidAssignmentTarget(self.previous().line, self.previous().column));
         }
         
         Ok(expr)
     }
 
+    fn range(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.or()?;
+        
+        if self.match_token(&TokenType::DotDot) {
+            let end = self.or()?;
+            return Ok(Expr::Range {
+                start: Box::new(expr),
+                end: Box::new(end),
+            });
+        }
+        
+        Ok(expr)
+    }
+
     fn or(&mut self) -> Result<Expr, ParseError> {
-        self.and()
+        let mut expr = self.and()?;
+        
+        while self.match_token(&TokenType::Or) {
+            let right = self.and()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator: LogicalOp::Or,
+                right: Box::new(right),
+            };
+        }
+        
+        Ok(expr)
     }
 
     fn and(&mut self) -> Result<Expr, ParseError> {
-        self.equality()
+        let mut expr = self.equality()?;
+        
+        while self.match_token(&TokenType::And) {
+            let right = self.equality()?;
+            expr = Expr::Logical {
+                left: Box::new(expr),
+                operator: LogicalOp::And,
+                right: Box::new(right),
+            };
+        }
+        
+        Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, ParseError> {
@@ -524,10 +754,11 @@ impl Parser {
     fn factor(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.unary()?;
         
-        while self.match_tokens(&[TokenType::Divide, TokenType::Multiply]) {
+        while self.match_tokens(&[TokenType::Divide, TokenType::Multiply, TokenType::Modulo]) {
             let operator = match self.previous().token_type {
                 TokenType::Divide => BinaryOp::Divide,
                 TokenType::Multiply => BinaryOp::Multiply,
+                TokenType::Modulo => BinaryOp::Modulo,
                 _ => unreachable!(),
             };
             let right = self.unary()?;
@@ -542,6 +773,11 @@ impl Parser {
     }
 
     fn unary(&mut self) -> Result<Expr, ParseError> {
+        // A leading '+' only marks a value as positive: set pitch ball1 +7
+        if self.match_tokens(&[TokenType::Plus]) {
+            return self.unary();
+        }
+        
         if self.match_tokens(&[TokenType::Minus]) {
             let operator = UnaryOp::Minus;
             let right = self.unary()?;
@@ -554,17 +790,78 @@ impl Parser {
         self.call()
     }
 
+    // One item of a space-separated list (slice steps, fx parameters). Each item is a single
+    // operand, so "0 -5 7" is three items rather than 0 - 5 and "1 (2)" isn't a call on 1;
+    // arithmetic goes in parentheses: slice 1 (i + 1)
+    fn spaced_operand(&mut self) -> Result<Expr, ParseError> {
+        if self.match_tokens(&[TokenType::Plus]) {
+            return self.spaced_operand();
+        }
+        
+        if self.match_tokens(&[TokenType::Minus]) {
+            let operand = self.spaced_operand()?;
+            return Ok(Expr::Unary {
+                operator: UnaryOp::Minus,
+                operand: Box::new(operand),
+            });
+        }
+        
+        // Only names take calls, indexing and properties: steps[0], self.hits
+        match self.peek().token_type {
+            TokenType::Identifier(_) | TokenType::Self_ => self.call(),
+            _ => self.primary(),
+        }
+    }
+
     fn call(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
         let mut expr = self.primary()?;
         
-        while self.match_token(&TokenType::LeftParen) {
-            expr = self.finish_call(expr)?;
+        loop {
+            if self.match_token(&TokenType::LeftParen) {
+                expr = self.finish_call(expr, span)?;
+            } else if self.match_token(&TokenType::LeftBracket) {
+                let index = self.expression()?;
+                self.consume(&TokenType::RightBracket, "Expected ']' after index")?;
+                expr = Expr::Index {
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                };
+            } else if self.match_token(&TokenType::Dot) {
+                let property = self.property_name()?;
+                expr = Expr::Get {
+                    object: Box::new(expr),
+                    property,
+                };
+            } else {
+                break;
+            }
         }
         
         Ok(expr)
     }
 
-    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+    // Name after a '.', which may also be a keyword like speed, color or hits
+    fn property_name(&mut self) -> Result<String, ParseError> {
+        let name = match &self.peek().token_type {
+            TokenType::Identifier(name) => name.clone(),
+            TokenType::Speed => "speed".to_string(),
+            TokenType::Color => "color".to_string(),
+            TokenType::Direction => "direction".to_string(),
+            TokenType::Hits => "hits".to_string(),
+            TokenType::Label => "label".to_string(),
+            TokenType::Script => "script".to_string(),
+            _ => return Err(ParseError::Expected {
+                expected: "property name".to_string(),
+                found: self.peek().clone(),
+                message: "Expected a property name after '.'".to_string(),
+            }),
+        };
+        self.advance();
+        Ok(name)
+    }
+
+    fn finish_call(&mut self, callee: Expr, span: Span) -> Result<Expr, ParseError> {
         let mut arguments = Vec::new();
         
         if !self.check(&TokenType::RightParen) {
@@ -581,6 +878,7 @@ impl Parser {
         Ok(Expr::Call {
             callee: Box::new(callee),
             arguments,
+            span,
         })
     }
 
@@ -615,6 +913,23 @@ impl Parser {
                 self.consume(&TokenType::RightParen, "Expected ')' after expression")?;
                 Ok(expr)
             },
+            TokenType::LeftBracket => {
+                self.advance();
+                let mut elements = Vec::new();
+                self.skip_newlines();
+                if !self.check(&TokenType::RightBracket) {
+                    loop {
+                        elements.push(self.expression()?);
+                        self.skip_newlines();
+                        if !self.match_token(&TokenType::Comma) {
+                            break;
+                        }
+                        self.skip_newlines();
+                    }
+                }
+                self.consume(&TokenType::RightBracket, "Expected ']' after list items")?;
+                Ok(Expr::List(elements))
+            },
             TokenType::Speed => {
                 // Allow 'speed' to be used as a function name
                 self.advance();
@@ -634,6 +949,17 @@ impl Parser {
     }
 
     // Helper methods
+    fn span(&self) -> Span {
+        let token = self.peek();
+        Span { line: token.line, column: token.column }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.check(&TokenType::Newline) {
+            self.advance();
+        }
+    }
+
     fn match_token(&mut self, token_type: &TokenType) -> bool {
         if self.check(token_type) {
             self.advance();
@@ -698,6 +1024,9 @@ impl Parser {
                 self.advance();
             }
             Ok(())
+        } else if self.check(&TokenType::RightBrace) {
+            // Last statement of a one-line block: the '}' is left for block() to consume
+            Ok(())
         } else {
             Err(ParseError::Expected {
                 expected: "newline or semicolon".to_string(),
@@ -798,6 +1127,7 @@ impl Parser {
                 self.advance();
                 text
             },
+            TokenType::On => { self.advance(); "on".to_string() },
             // Accept color tokens as valid text for labels
             TokenType::Red => { self.advance(); "red".to_string() },
             TokenType::Blue => { self.advance(); "blue".to_string() },
@@ -888,32 +1218,48 @@ impl Parser {
     }
     
     fn slice_statement(&mut self) -> Result<Stmt, ParseError> {
+        // slice into 16, slice into 8 ball2, slice into 4 "loop.wav"
+        if matches!(&self.peek().token_type, TokenType::Identifier(name) if name == "into") {
+            self.advance();
+            let divisions = self.spaced_operand()?;
+            let target = if self.check(&TokenType::Newline) || self.check(&TokenType::Semicolon)
+                || self.check(&TokenType::RightBrace) || self.is_at_end() {
+                None
+            } else {
+                Some(self.spaced_operand()?)
+            };
+            self.consume_newline_or_semicolon()?;
+            return Ok(Stmt::SliceInto { divisions, target });
+        }
+        
         let mut sequence = Vec::new();
+        let mut pitches = Vec::new();
         
-        // Parse the sequence of numbers
-        while !self.check(&TokenType::Newline) && !self.check(&TokenType::Semicolon) && !self.is_at_end() {
-            if let TokenType::Number(n) = &self.peek().token_type {
-                sequence.push(*n);
-                self.advance();
-            } else {
-                return Err(ParseError::Expected {
-                    expected: "number".to_string(),
-                    found: self.peek().clone(),
-                    message: "Expected number in slice sequence".to_string(),
-                });
+        // Parse the sequence: numbers, or expressions giving numbers or lists (slice 1 3 2, slice steps),
+        // optionally followed by semitones per step (slice 1 2 3 pitch 0 7 12)
+        while !self.check(&TokenType::Newline) && !self.check(&TokenType::Semicolon)
+            && !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if matches!(&self.peek().token_type, TokenType::Identifier(name) if name == "pitch") && pitches.is_empty() {
+                self.advance(); // consume 'pitch'
+                while !self.check(&TokenType::Newline) && !self.check(&TokenType::Semicolon)
+                    && !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+                    pitches.push(self.spaced_operand()?);
+                }
+                break;
             }
+            sequence.push(self.spaced_operand()?);
         }
         
         if sequence.is_empty() {
             return Err(ParseError::Expected {
                 expected: "slice sequence".to_string(),
                 found: self.peek().clone(),
-                message: "Slice command requires at least one number".to_string(),
+                message: "Slice command requires at least one number or list".to_string(),
             });
         }
         
         self.consume_newline_or_semicolon()?;
-        Ok(Stmt::Slice { sequence })
+        Ok(Stmt::Slice { sequence, pitches })
     }
 
     fn waveform_statement(&mut self) -> Result<Stmt, ParseError> {
@@ -1003,7 +1349,8 @@ impl Parser {
     fn parse_implicit_block(&mut self) -> Result<Vec<Stmt>, ParseError> {
         let mut statements = Vec::new();
         
-        while !self.is_at_end() && !self.is_block_initiator() {
+        // A '}' closes the enclosing braced block as well
+        while !self.is_at_end() && !self.is_block_initiator() && !self.check(&TokenType::RightBrace) {
             if self.check(&TokenType::Newline) {
                 self.advance();
                 continue;
@@ -1057,4 +1404,80 @@ impl fmt::Display for ParseError {
     }
 }
 
-impl std::error::Error for ParseError {}
\ No newline at end of file
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn parse_statement(source: &str) -> Stmt {
+        let tokens = Lexer::new(source).tokenize().expect("lexes");
+        let mut program = Parser::new(tokens).parse().expect("parses");
+        assert_eq!(program.statements.len(), 1);
+        program.statements.remove(0).inner().clone()
+    }
+
+    // Plain numbers, negative or not, as the parser leaves them
+    fn numbers(exprs: &[Expr]) -> Vec<f64> {
+        exprs.iter().map(|expr| match expr {
+            Expr::Number(n) => *n,
+            Expr::Unary { operator: UnaryOp::Minus, operand } => match operand.as_ref() {
+                Expr::Number(n) => -n,
+                other => panic!("expected a number, got {:?}", other),
+            },
+            other => panic!("expected a number, got {:?}", other),
+        }).collect()
+    }
+
+    #[test]
+    fn slice_pitches_keep_negative_steps_apart() {
+        match parse_statement("slice 1 2 3 pitch 0 -5 7") {
+            Stmt::Slice { sequence, pitches } => {
+                assert_eq!(numbers(&sequence), vec![1.0, 2.0, 3.0]);
+                assert_eq!(numbers(&pitches), vec![0.0, -5.0, 7.0]);
+            },
+            other => panic!("expected a slice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slice_steps_are_single_operands() {
+        match parse_statement("slice 1 -2 (2 + 1) [4, 5] steps[0]") {
+            Stmt::Slice { sequence, pitches } => {
+                assert_eq!(sequence.len(), 5);
+                assert_eq!(numbers(&sequence[..2]), vec![1.0, -2.0]);
+                assert!(matches!(&sequence[2], Expr::Binary { operator: BinaryOp::Add, .. }));
+                assert!(matches!(&sequence[3], Expr::List(items) if items.len() == 2));
+                assert!(matches!(&sequence[4], Expr::Index { .. }));
+                assert!(pitches.is_empty());
+            },
+            other => panic!("expected a slice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fx_parameters_keep_negative_values_apart() {
+        match parse_statement("fx ball1 delay 0.25 -0.4 0.5") {
+            Stmt::Fx { target, effect, arguments, remove } => {
+                assert_eq!((target.as_str(), effect.as_str(), remove), ("ball1", "delay", false));
+                assert_eq!(numbers(&arguments), vec![0.25, -0.4, 0.5]);
+            },
+            other => panic!("expected fx, got {:?}", other),
+        }
+    }
+    #[test]
+    fn slice_into_takes_a_count_and_an_optional_target() {
+        match parse_statement("slice into 16") {
+            Stmt::SliceInto { divisions, target } => {
+                assert_eq!(numbers(&[divisions]), vec![16.0]);
+                assert!(target.is_none());
+            },
+            other => panic!("expected slice into, got {:?}", other),
+        }
+        match parse_statement("slice into 8 \"loop.wav\"") {
+            Stmt::SliceInto { target: Some(Expr::String(file)), .. } => assert_eq!(file, "loop.wav"),
+            other => panic!("expected slice into a file, got {:?}", other),
+        }
+    }
+}
\ No newline at end of file
@@ -2,32 +2,75 @@ use crate::ball::Ball;
 use crate::square::Square;
 use crate::grid::GridState;
 
+// Tolerance used to treat two face hits as simultaneous (a corner hit)
+const CORNER_EPSILON: f64 = 1e-9;
+
 pub struct PhysicsEngine {
     pub grid_width: f64,
     pub grid_height: f64,
     pub tile_size: f64,
+    pub substeps: u32, // Number of sub-steps each fixed tick is divided into
 }
 
 pub struct CollisionInfo {
     pub ball_id: u32,
     pub collision_type: CollisionType,
-    pub other_object_id: Option<u32>, // For square collisions
+    pub other_object_id: Option<u32>, // For square and ball collisions
+    pub time: f64, // Seconds into the step at which the collision happened
+    pub position: (f64, f64), // Where the ball was when it hit, in grid units
 }
 
 pub enum CollisionType {
     Wall,
     Square,
+    Ball,
 }
 
 enum CollisionResult {
     None,
-    Square { collision_point: (f64, f64), square_id: u32 },
+    Square { collision_point: (f64, f64), normal: (f64, f64), square_id: u32 },
     Wall { collision_point: (f64, f64) },
 }
 
 impl PhysicsEngine {
     pub fn new(grid_width: f64, grid_height: f64, tile_size: f64) -> Self {
-        Self { grid_width, grid_height, tile_size }
+        Self { grid_width, grid_height, tile_size, substeps: 1 }
+    }
+    
+    pub fn set_substeps(&mut self, substeps: u32) {
+        self.substeps = substeps.max(1);
+    }
+    
+    /// Advances every ball by one fixed tick, split into `substeps` equal sub-steps.
+    /// Balls are processed in the order given, so callers should pass them sorted by id
+    /// for the collision sequence to be reproducible.
+    pub fn step(&self, balls: &mut [&mut Ball], squares: &[Square], dt: f64) -> Vec<CollisionInfo> {
+        let mut collisions = Vec::new();
+        let substeps = self.substeps.max(1);
+        let sub_dt = dt / substeps as f64;
+        
+        for substep in 0..substeps {
+            let offset = sub_dt * substep as f64;
+            let mut substep_collisions = Vec::new();
+            for ball in balls.iter_mut() {
+                substep_collisions.extend(self.update_ball(ball, sub_dt, squares));
+            }
+            
+            // Ball contacts are found once every ball has moved, i.e. at the end of the sub-step
+            let mut ball_contacts = self.resolve_ball_collisions(balls);
+            for contact in &mut ball_contacts {
+                contact.time = sub_dt;
+            }
+            substep_collisions.extend(ball_contacts);
+            
+            // Make collision times relative to the start of the whole step
+            for collision in &mut substep_collisions {
+                collision.time += offset;
+            }
+            collisions.extend(substep_collisions);
+        }
+        
+        collisions
     }
     
     // Add this method to update boundaries when grid changes
@@ -46,56 +89,56 @@ impl PhysicsEngine {
         // Check for collisions along the movement path
         let collision_result = self.check_collision_path(ball, new_x, new_y, squares);
         
+        // Time within this step at which the ball reaches a given point on its path
+        let travel = ((new_x - ball.x).powi(2) + (new_y - ball.y).powi(2)).sqrt();
+        let time_to = |point: (f64, f64), ball: &Ball| -> f64 {
+            if travel > 0.0 {
+                dt * (((point.0 - ball.x).powi(2) + (point.1 - ball.y).powi(2)).sqrt() / travel).min(1.0)
+            } else {
+                0.0
+            }
+        };
+        
         match collision_result {
             CollisionResult::None => {
                 // No collision, move to intended position
                 ball.x = new_x;
                 ball.y = new_y;
             },
-            CollisionResult::Square { collision_point, square_id } => {
+            CollisionResult::Square { collision_point, normal, square_id } => {
+                let time = time_to(collision_point, ball);
+                
                 // Move to exact collision point
                 ball.x = collision_point.0;
                 ball.y = collision_point.1;
                 
-                // Handle collision physics
-                ball.velocity_x = -ball.velocity_x;
-                ball.velocity_y = -ball.velocity_y;
+                // Reflect only the velocity component along the struck face's normal.
+                // A corner hit has a normal on both axes and reverses both components.
+                if normal.0 != 0.0 {
+                    ball.velocity_x = -ball.velocity_x;
+                }
+                if normal.1 != 0.0 {
+                    ball.velocity_y = -ball.velocity_y;
+                }
                 
-                // Add separation to prevent getting stuck
-                let ball_radius = 0.4;
+                // Add separation along the face normal to prevent getting stuck
                 let separation_distance = 0.01; // Small separation to prevent overlap
-                
-                // Find the square that was hit
-                if let Some(square) = squares.iter().find(|s| s.id == square_id) {
-                    let square_center_x = square.x + 0.5;
-                    let square_center_y = square.y + 0.5;
-                    
-                    // Calculate direction from square center to ball
-                    let dx = ball.x - square_center_x;
-                    let dy = ball.y - square_center_y;
-                    let distance = (dx * dx + dy * dy).sqrt();
-                    
-                    if distance > 0.0 {
-                        // Normalize and apply separation
-                        let norm_dx = dx / distance;
-                        let norm_dy = dy / distance;
-                        
-                        // Move ball away from square by separation distance
-                        ball.x += norm_dx * separation_distance;
-                        ball.y += norm_dy * separation_distance;
-                    }
-                }
+                ball.x += normal.0 * separation_distance;
+                ball.y += normal.1 * separation_distance;
                 
                 ball.update_direction_from_velocity();
-                ball.play_collision_audio();
                 
                 collisions.push(CollisionInfo {
                     ball_id: ball.id,
                     collision_type: CollisionType::Square,
                     other_object_id: Some(square_id),
+                    time,
+                    position: collision_point,
                 });
             },
             CollisionResult::Wall { collision_point } => {
+                let time = time_to(collision_point, ball);
+                
                 // Move to exact collision point
                 ball.x = collision_point.0;
                 ball.y = collision_point.1;
@@ -122,12 +165,76 @@ impl PhysicsEngine {
                 }
                 
                 ball.update_direction_from_velocity();
-                ball.play_collision_audio();
                 
                 collisions.push(CollisionInfo {
                     ball_id: ball.id,
                     collision_type: CollisionType::Wall,
                     other_object_id: None,
+                    time,
+                    position: collision_point,
+                });
+            }
+        }
+        
+        collisions
+    }
+    
+    /// Resolves overlapping ball pairs with an equal-mass elastic collision.
+    /// Each contact produces one CollisionInfo per ball, pointing at the other ball.
+    pub fn resolve_ball_collisions(&self, balls: &mut [&mut Ball]) -> Vec<CollisionInfo> {
+        let mut collisions = Vec::new();
+        let ball_radius = 0.4;
+        let separation_distance = 0.01; // Small separation to prevent overlap
+        
+        for i in 0..balls.len() {
+            for j in (i + 1)..balls.len() {
+                let (head, tail) = balls.split_at_mut(j);
+                let a = &mut *head[i];
+                let b = &mut *tail[0];
+                
+                let dx = b.x - a.x;
+                let dy = b.y - a.y;
+                let distance = (dx * dx + dy * dy).sqrt();
+                
+                if distance >= ball_radius * 2.0 {
+                    continue;
+                }
+                
+                // Collision normal from a to b (fall back to the x axis for coincident centres)
+                let (nx, ny) = if distance > 0.0 { (dx / distance, dy / distance) } else { (1.0, 0.0) };
+                
+                // Only respond when the balls are approaching each other
+                let approach_speed = (a.velocity_x - b.velocity_x) * nx + (a.velocity_y - b.velocity_y) * ny;
+                if approach_speed <= 0.0 {
+                    continue;
+                }
+                
+                let (contact_a, contact_b) = ((a.x, a.y), (b.x, b.y));
+                
+                // Equal masses: exchange the velocity components along the normal
+                a.set_velocity(a.velocity_x - approach_speed * nx, a.velocity_y - approach_speed * ny);
+                b.set_velocity(b.velocity_x + approach_speed * nx, b.velocity_y + approach_speed * ny);
+                
+                // Push the balls apart so they no longer overlap
+                let push = (ball_radius * 2.0 - distance) / 2.0 + separation_distance;
+                a.x = (a.x - nx * push).clamp(ball_radius, self.grid_width - ball_radius);
+                a.y = (a.y - ny * push).clamp(ball_radius, self.grid_height - ball_radius);
+                b.x = (b.x + nx * push).clamp(ball_radius, self.grid_width - ball_radius);
+                b.y = (b.y + ny * push).clamp(ball_radius, self.grid_height - ball_radius);
+                
+                collisions.push(CollisionInfo {
+                    ball_id: a.id,
+                    collision_type: CollisionType::Ball,
+                    other_object_id: Some(b.id),
+                    time: 0.0,
+                    position: contact_a,
+                });
+                collisions.push(CollisionInfo {
+                    ball_id: b.id,
+                    collision_type: CollisionType::Ball,
+                    other_object_id: Some(a.id),
+                    time: 0.0,
+                    position: contact_b,
                 });
             }
         }
@@ -138,16 +245,25 @@ impl PhysicsEngine {
     fn check_collision_path(&self, ball: &Ball, target_x: f64, target_y: f64, squares: &[Square]) -> CollisionResult {
         let ball_radius = 0.4;
         
-        // Check square collisions first (they take priority)
+        // Check square collisions first (they take priority), keeping the one reached first
+        let mut nearest: Option<(f64, CollisionResult)> = None;
         for square in squares {
-            if let Some(collision_point) = self.calculate_collision_point(ball, target_x, target_y, square, ball_radius) {
-                return CollisionResult::Square { 
-                    collision_point, 
-                    square_id: square.id 
-                };
+            if let Some((collision_point, normal)) = self.calculate_collision_point(ball, target_x, target_y, square, ball_radius) {
+                let distance = (collision_point.0 - ball.x).powi(2) + (collision_point.1 - ball.y).powi(2);
+                if nearest.as_ref().map_or(true, |(best, _)| distance < *best) {
+                    nearest = Some((distance, CollisionResult::Square { 
+                        collision_point, 
+                        normal,
+                        square_id: square.id 
+                    }));
+                }
             }
         }
         
+        if let Some((_, result)) = nearest {
+            return result;
+        }
+        
         // Check wall collisions
         if let Some(collision_point) = self.calculate_wall_collision_point(ball, target_x, target_y, ball_radius) {
             return CollisionResult::Wall { collision_point };
@@ -156,7 +272,9 @@ impl PhysicsEngine {
         CollisionResult::None
     }
     
-    fn calculate_collision_point(&self, ball: &Ball, target_x: f64, target_y: f64, square: &Square, ball_radius: f64) -> Option<(f64, f64)> {
+    /// Returns the point where the ball first touches the square along its path,
+    /// together with the outward normal of the face that was struck.
+    fn calculate_collision_point(&self, ball: &Ball, target_x: f64, target_y: f64, square: &Square, ball_radius: f64) -> Option<((f64, f64), (f64, f64))> {
         // Ray-box intersection to find exact collision point
         let square_left = square.x;
         let square_right = square.x + 1.0;
@@ -177,30 +295,46 @@ impl PhysicsEngine {
             return None;
         }
         
-        // Calculate intersection times for each edge
-        let t_left = if dx != 0.0 { (expanded_left - ball.x) / dx } else { f64::INFINITY };
-        let t_right = if dx != 0.0 { (expanded_right - ball.x) / dx } else { f64::INFINITY };
-        let t_top = if dy != 0.0 { (expanded_top - ball.y) / dy } else { f64::INFINITY };
-        let t_bottom = if dy != 0.0 { (expanded_bottom - ball.y) / dy } else { f64::INFINITY };
+        // Calculate intersection times for each edge. A face can only be entered
+        // when moving towards it, so faces on the far side are ignored.
+        let t_left = if dx > 0.0 { (expanded_left - ball.x) / dx } else { f64::INFINITY };
+        let t_right = if dx < 0.0 { (expanded_right - ball.x) / dx } else { f64::INFINITY };
+        let t_top = if dy > 0.0 { (expanded_top - ball.y) / dy } else { f64::INFINITY };
+        let t_bottom = if dy < 0.0 { (expanded_bottom - ball.y) / dy } else { f64::INFINITY };
+        
+        let faces = [
+            (t_left, (-1.0, 0.0)),
+            (t_right, (1.0, 0.0)),
+            (t_top, (0.0, -1.0)),
+            (t_bottom, (0.0, 1.0)),
+        ];
         
         // Find the earliest valid intersection
         let mut min_t = f64::INFINITY;
+        let mut normal = (0.0, 0.0);
         
-        for &t in &[t_left, t_right, t_top, t_bottom] {
-            if t >= 0.0 && t <= 1.0 && t < min_t {
+        for &(t, face_normal) in &faces {
+            if t >= 0.0 && t <= 1.0 && t <= min_t + CORNER_EPSILON {
                 let collision_x = ball.x + dx * t;
                 let collision_y = ball.y + dy * t;
                 
                 // Verify the collision point is actually on the square boundary
-                if collision_x >= expanded_left && collision_x <= expanded_right &&
-                   collision_y >= expanded_top && collision_y <= expanded_bottom {
-                    min_t = t;
+                if collision_x >= expanded_left - CORNER_EPSILON && collision_x <= expanded_right + CORNER_EPSILON &&
+                   collision_y >= expanded_top - CORNER_EPSILON && collision_y <= expanded_bottom + CORNER_EPSILON {
+                    if (t - min_t).abs() <= CORNER_EPSILON {
+                        // Both faces are reached at the same instant: corner hit
+                        normal = (normal.0 + face_normal.0, normal.1 + face_normal.1);
+                        min_t = min_t.min(t);
+                    } else if t < min_t {
+                        min_t = t;
+                        normal = face_normal;
+                    }
                 }
             }
         }
         
         if min_t < f64::INFINITY {
-            Some((ball.x + dx * min_t, ball.y + dy * min_t))
+            Some(((ball.x + dx * min_t, ball.y + dy * min_t), normal))
         } else {
             None
         }
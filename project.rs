@@ -0,0 +1,414 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::synth::SynthSettings;
+use crate::effects::EffectSettings;
+
+// Bump when the layout changes in a way older builds can't read
+pub const PROJECT_VERSION: u32 = 1;
+pub const PROJECTS_DIR: &str = "projects";
+pub const SAMPLES_DIR: &str = "samples";
+pub const MARKERS_VERSION: u32 = 1;
+
+// Everything needed to rebuild a scene, as stored in projects/<name>.json
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectFile {
+    pub version: u32,
+    #[serde(default)]
+    pub grid: Option<GridData>,
+    #[serde(default)]
+    pub balls: Vec<BallData>,
+    #[serde(default)]
+    pub squares: Vec<SquareData>,
+    #[serde(default)]
+    pub memory_scripts: BTreeMap<String, String>, // script_name -> script_content
+    #[serde(default)]
+    pub samples: Vec<SampleData>,
+    #[serde(default)]
+    pub master_effects: Vec<EffectSettings>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridData {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub center_origin: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BallData {
+    pub name: String, // ball1, ball2, ... so scripts keep referring to the same balls
+    pub x: f64,
+    pub y: f64,
+    pub speed: f64,
+    pub direction: f64, // angle in radians
+    pub color: String,
+    #[serde(default)]
+    pub audio_file: Option<String>, // Relative to samples/
+    #[serde(default = "default_volume")]
+    pub volume: f32,
+    #[serde(default)]
+    pub pitch: f64, // Semitones
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+    #[serde(default)]
+    pub pan: Option<f64>, // None follows the pan mode
+    #[serde(default)]
+    pub synth: Option<SynthSettings>,
+    #[serde(default)]
+    pub effects: Vec<EffectSettings>,
+    #[serde(default)]
+    pub midi_note: Option<u8>,
+    #[serde(default)]
+    pub script: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SquareData {
+    pub name: String,
+    pub x: f64,
+    pub y: f64,
+    pub color: String,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub midi_note: Option<u8>,
+    #[serde(default)]
+    pub script: Option<String>,
+}
+
+// Slice markers of one loaded sample
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleData {
+    pub file: String, // Relative to samples/
+    #[serde(default)]
+    pub slice_markers: Vec<f64>, // Seconds
+}
+
+// Slice markers of one sample file, kept next to it as <file>.markers.json.
+// The sample rate and hash tell whether the audio changed since they were saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkerFile {
+    pub version: u32,
+    pub sample_rate: u32,
+    pub hash: String, // FNV-1a 64 of the sample file, as hex
+    pub slice_markers: Vec<f64>, // Seconds
+}
+
+impl MarkerFile {
+    pub fn new(sample_rate: u32, content_hash: u64, slice_markers: Vec<f64>) -> Self {
+        Self {
+            version: MARKERS_VERSION,
+            sample_rate,
+            hash: format_hash(content_hash),
+            slice_markers,
+        }
+    }
+
+    // Markers saved for a file with this content and rate, so still valid
+    pub fn matches(&self, sample_rate: u32, content_hash: u64) -> bool {
+        self.sample_rate == sample_rate && self.hash == format_hash(content_hash)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Cannot serialize markers: {}", e))?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Cannot write markers {}: {}", path.display(), e))
+    }
+
+    /// The sidecar at `path`, or None if the sample has none.
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read markers {}: {}", path.display(), e))?;
+        let markers: MarkerFile = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid markers file {}: {}", path.display(), e))?;
+        if markers.version > MARKERS_VERSION {
+            return Err(format!("Markers {} were saved with a newer version ({})", path.display(), markers.version));
+        }
+        Ok(Some(markers))
+    }
+}
+
+// samples/kick.wav -> samples/kick.wav.markers.json
+pub fn markers_path(sample_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.markers.json", sample_path))
+}
+
+// 64-bit FNV-1a; enough to notice a sample file was replaced or edited
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn format_hash(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+fn default_volume() -> f32 {
+    1.0
+}
+
+fn default_rate() -> f64 {
+    1.0
+}
+
+impl ProjectFile {
+    pub fn new() -> Self {
+        Self {
+            version: PROJECT_VERSION,
+            grid: None,
+            balls: Vec::new(),
+            squares: Vec::new(),
+            memory_scripts: BTreeMap::new(),
+            samples: Vec::new(),
+            master_effects: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Cannot create directory {}: {}", parent.display(), e))?;
+            }
+        }
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Cannot serialize project: {}", e))?;
+        std::fs::write(path, json)
+            .map_err(|e| format!("Cannot write project {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read project {}: {}", path.display(), e))?;
+        let project: ProjectFile = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid project file {}: {}", path.display(), e))?;
+        if project.version > PROJECT_VERSION {
+            return Err(format!(
+                "Project {} was saved with a newer version ({}); this build reads up to version {}",
+                path.display(), project.version, PROJECT_VERSION
+            ));
+        }
+        Ok(project)
+    }
+}
+
+/// Where a project called `name` lives: `projects/<name>.json`, unless the
+/// name is already a path to a .json file.
+pub fn project_path(name: &str) -> PathBuf {
+    let path = Path::new(name);
+    if path.extension().map_or(false, |ext| ext == "json") {
+        path.to_path_buf()
+    } else {
+        Path::new(PROJECTS_DIR).join(format!("{}.json", name))
+    }
+}
+
+// Sample path as stored in a project: relative to samples/
+pub fn sample_to_project_path(sample_path: &str) -> String {
+    let path = Path::new(sample_path);
+    match path.strip_prefix(SAMPLES_DIR) {
+        Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+        Err(_) => path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| sample_path.to_string()),
+    }
+}
+
+// Path a project's sample reference is loaded from
+pub fn sample_from_project_path(file: &str) -> String {
+    format!("{}/{}", SAMPLES_DIR, file)
+}
+
+// Parse "ball3" / "square12" into the sequence number 3 / 12
+pub fn sequence_from_name(name: &str, prefix: &str) -> Option<u32> {
+    name.strip_prefix(prefix)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fnv1a_matches_the_reference_values() {
+        assert_eq!(fnv1a_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn marker_files_match_only_the_same_audio() {
+        let markers = MarkerFile::new(44100, fnv1a_hash(b"kick"), vec![0.5]);
+        assert!(markers.matches(44100, fnv1a_hash(b"kick")));
+        assert!(!markers.matches(48000, fnv1a_hash(b"kick")));
+        assert!(!markers.matches(44100, fnv1a_hash(b"snare")));
+    }
+
+    #[test]
+    fn marker_files_round_trip() {
+        let directory = std::env::temp_dir().join(format!("cant_markers_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = markers_path(&directory.join("loop.wav").to_string_lossy());
+        assert!(path.to_string_lossy().ends_with("loop.wav.markers.json"));
+        std::fs::remove_file(&path).ok();
+        assert!(MarkerFile::load(&path).unwrap().is_none());
+
+        MarkerFile::new(44100, 42, vec![0.25, 1.5]).save(&path).unwrap();
+        let loaded = MarkerFile::load(&path).unwrap().unwrap();
+        assert!(loaded.matches(44100, 42));
+        assert_eq!(loaded.slice_markers, vec![0.25, 1.5]);
+
+        // Saving no markers leaves a file that clears the old ones
+        MarkerFile::new(44100, 42, Vec::new()).save(&path).unwrap();
+        assert!(MarkerFile::load(&path).unwrap().unwrap().slice_markers.is_empty());
+        std::fs::remove_dir_all(&directory).ok();
+    }
+    fn ball(name: &str) -> BallData {
+        BallData {
+            name: name.to_string(),
+            x: 3.0,
+            y: 4.0,
+            speed: 2.5,
+            direction: 0.5,
+            color: "Red".to_string(),
+            audio_file: Some("drums/kick.wav".to_string()),
+            volume: 0.8,
+            pitch: 7.0,
+            rate: 0.5,
+            pan: Some(-0.25),
+            synth: None,
+            effects: Vec::new(),
+            midi_note: Some(60),
+            script: Some("on wall { set pitch self +1 }".to_string()),
+        }
+    }
+
+    #[test]
+    fn projects_round_trip_through_a_file() {
+        let directory = std::env::temp_dir().join(format!("cant_project_{}", std::process::id()));
+        std::fs::remove_dir_all(&directory).ok();
+        let path = directory.join("projects").join("song.json");
+
+        let mut synth_ball = ball("ball2");
+        synth_ball.audio_file = None;
+        synth_ball.pan = None;
+        let mut synth = SynthSettings::new(crate::synth::Waveform::Saw);
+        synth.frequency = 220.0;
+        synth.release = 0.5;
+        synth_ball.synth = Some(synth);
+        synth_ball.effects = vec![
+            EffectSettings::Lowpass { cutoff: 800.0, resonance: 2.0 },
+            EffectSettings::Delay { time: 0.25, feedback: 0.5, mix: 0.5 },
+        ];
+
+        let mut project = ProjectFile::new();
+        project.grid = Some(GridData { width: 16, height: 12, center_origin: Some(1) });
+        project.balls = vec![ball("ball1"), synth_ball];
+        project.squares = vec![SquareData {
+            name: "square1".to_string(),
+            x: 5.0,
+            y: 6.0,
+            color: "Blue".to_string(),
+            label: Some("snare".to_string()),
+            midi_note: Some(38),
+            script: None,
+        }];
+        project.samples = vec![SampleData {
+            file: "drums/kick.wav".to_string(),
+            slice_markers: vec![0.125, 0.5, 1.75],
+        }];
+        project.master_effects = vec![EffectSettings::Reverb { size: 0.75, mix: 0.25 }];
+        // save creates the missing projects/ directory
+        project.save(&path).unwrap();
+
+        let loaded = ProjectFile::load(&path).unwrap();
+        std::fs::remove_dir_all(&directory).ok();
+        assert_eq!(loaded.version, PROJECT_VERSION);
+        let grid = loaded.grid.unwrap();
+        assert_eq!((grid.width, grid.height, grid.center_origin), (16, 12, Some(1)));
+
+        assert_eq!(loaded.balls.len(), 2);
+        let sample_ball = &loaded.balls[0];
+        assert_eq!(sample_ball.name, "ball1");
+        assert_eq!((sample_ball.x, sample_ball.y, sample_ball.speed, sample_ball.direction), (3.0, 4.0, 2.5, 0.5));
+        assert_eq!(sample_ball.audio_file.as_deref(), Some("drums/kick.wav"));
+        assert_eq!(sample_ball.volume, 0.8);
+        assert_eq!((sample_ball.pitch, sample_ball.rate, sample_ball.pan), (7.0, 0.5, Some(-0.25)));
+        assert_eq!(sample_ball.midi_note, Some(60));
+        assert_eq!(sample_ball.script.as_deref(), Some("on wall { set pitch self +1 }"));
+        assert!(sample_ball.synth.is_none());
+
+        let synth_ball = &loaded.balls[1];
+        assert_eq!(synth_ball.pan, None);
+        let synth = synth_ball.synth.as_ref().unwrap();
+        assert_eq!(synth.waveform, crate::synth::Waveform::Saw);
+        assert_eq!((synth.frequency, synth.release), (220.0, 0.5));
+        assert_eq!(synth_ball.effects, vec![
+            EffectSettings::Lowpass { cutoff: 800.0, resonance: 2.0 },
+            EffectSettings::Delay { time: 0.25, feedback: 0.5, mix: 0.5 },
+        ]);
+
+        assert_eq!(loaded.squares.len(), 1);
+        assert_eq!(loaded.squares[0].name, "square1");
+        assert_eq!(loaded.squares[0].label.as_deref(), Some("snare"));
+        assert_eq!(loaded.squares[0].midi_note, Some(38));
+        assert_eq!(loaded.samples.len(), 1);
+        assert_eq!(loaded.samples[0].file, "drums/kick.wav");
+        assert_eq!(loaded.samples[0].slice_markers, vec![0.125, 0.5, 1.75]);
+        assert_eq!(loaded.master_effects, vec![EffectSettings::Reverb { size: 0.75, mix: 0.25 }]);
+    }
+
+    #[test]
+    fn version_1_files_without_newer_fields_still_load() {
+        let directory = std::env::temp_dir().join(format!("cant_project_v1_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("old.json");
+        std::fs::write(&path, r#"{
+            "version": 1,
+            "balls": [
+                { "name": "ball1", "x": 1.0, "y": 2.0, "speed": 3.0, "direction": 0.0, "color": "Green" }
+            ],
+            "squares": [
+                { "name": "square1", "x": 4.0, "y": 5.0, "color": "White" }
+            ]
+        }"#).unwrap();
+
+        let loaded = ProjectFile::load(&path).unwrap();
+        std::fs::remove_dir_all(&directory).ok();
+        assert!(loaded.grid.is_none());
+        assert!(loaded.memory_scripts.is_empty());
+        assert!(loaded.samples.is_empty());
+        assert!(loaded.master_effects.is_empty());
+
+        let ball = &loaded.balls[0];
+        assert_eq!(ball.name, "ball1");
+        assert_eq!(ball.audio_file, None);
+        assert_eq!((ball.volume, ball.pitch, ball.rate, ball.pan), (1.0, 0.0, 1.0, None));
+        assert!(ball.synth.is_none());
+        assert!(ball.effects.is_empty());
+        assert_eq!((ball.midi_note, ball.script.as_deref()), (None, None));
+
+        let square = &loaded.squares[0];
+        assert_eq!((square.label.as_deref(), square.midi_note, square.script.as_deref()), (None, None, None));
+    }
+
+    #[test]
+    fn projects_from_a_newer_version_are_rejected() {
+        let directory = std::env::temp_dir().join(format!("cant_project_v2_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("new.json");
+        std::fs::write(&path, format!("{{ \"version\": {} }}", PROJECT_VERSION + 1)).unwrap();
+        let result = ProjectFile::load(&path);
+        std::fs::remove_dir_all(&directory).ok();
+        assert!(result.unwrap_err().contains("newer version"));
+    }
+}
\ No newline at end of file
@@ -15,6 +15,7 @@ pub struct Square {
     pub script: Option<String>,
     pub color: String,
     pub label: Option<String>,
+    pub midi_note: Option<u8>, // note recorded by midi_record when a ball hits this square
     pub hit_counts: HashMap<u32, u32>, // object_id -> hit_count
 }
 
@@ -30,6 +31,7 @@ impl Square {
             script: None,
             color: "white".to_string(),
             label: None,
+            midi_note: None,
             hit_counts: HashMap::new(),
         }
     }
@@ -58,6 +60,12 @@ impl Square {
         format!("square{}", self.sequence_number)
     }
     
+    // Give a restored square its old name and keep new squares from reusing it
+    pub fn set_sequence_number(&mut self, sequence_number: u32) {
+        self.sequence_number = sequence_number;
+        SQUARE_SEQUENCE.fetch_max(sequence_number + 1, Ordering::SeqCst);
+    }
+    
     pub fn get_position(&self) -> (f64, f64) {
         (self.x, self.y)
     }
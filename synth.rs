@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+
+// Peak level of a note at full volume, leaving headroom when several play at once
+const SYNTH_LEVEL: f32 = 0.5;
+// Longest attack, decay, hold or release, so a typo can't render hours of audio
+pub const MAX_ENVELOPE_TIME: f64 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Noise,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sine" => Some(Waveform::Sine),
+            "square" => Some(Waveform::Square),
+            "saw" => Some(Waveform::Saw),
+            "noise" => Some(Waveform::Noise),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Sine => "sine",
+            Waveform::Square => "square",
+            Waveform::Saw => "saw",
+            Waveform::Noise => "noise",
+        }
+    }
+}
+
+// Oscillator and envelope of a ball that plays a synth note instead of a sample
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SynthSettings {
+    pub waveform: Waveform,
+    pub frequency: f64, // Base pitch in Hz, before the ball's pitch setting
+    pub attack: f64,    // Seconds
+    pub decay: f64,     // Seconds
+    pub sustain: f64,   // Level 0.0 - 1.0
+    pub release: f64,   // Seconds
+    #[serde(default = "default_hold")]
+    pub hold: f64,      // Seconds held at the sustain level; hits have no note-off
+}
+
+fn default_hold() -> f64 {
+    0.1
+}
+
+impl SynthSettings {
+    pub fn new(waveform: Waveform) -> Self {
+        Self {
+            waveform,
+            frequency: 440.0,
+            attack: 0.005,
+            decay: 0.1,
+            sustain: 0.6,
+            release: 0.2,
+            hold: default_hold(),
+        }
+    }
+
+    // Keeps envelope times within 0 - MAX_ENVELOPE_TIME seconds and sustain within 0 - 1,
+    // for settings typed into a script or read from a project file
+    pub fn clamped(mut self) -> Self {
+        for time in [&mut self.attack, &mut self.decay, &mut self.hold, &mut self.release] {
+            *time = time.clamp(0.0, MAX_ENVELOPE_TIME);
+        }
+        self.sustain = self.sustain.clamp(0.0, 1.0);
+        self
+    }
+
+    // Length of a whole note, from attack to the end of the release
+    pub fn duration(&self) -> f64 {
+        self.attack + self.decay + self.hold + self.release
+    }
+
+    // Envelope level `time` seconds into the note
+    fn envelope(&self, time: f64) -> f64 {
+        let decay_start = self.attack;
+        let hold_start = decay_start + self.decay;
+        let release_start = hold_start + self.hold;
+
+        if time < decay_start {
+            time / self.attack
+        } else if time < hold_start {
+            1.0 - (1.0 - self.sustain) * (time - decay_start) / self.decay
+        } else if time < release_start {
+            self.sustain
+        } else {
+            (self.sustain * (1.0 - (time - release_start) / self.release)).max(0.0)
+        }
+    }
+
+    /// Identifies the PCM of one note, so repeated hits at the same pitch reuse it.
+    pub fn note_key(&self, frequency: f64) -> String {
+        format!(
+            "synth:{}:{:.2}:{}:{}:{}:{}:{}",
+            self.waveform.name(), frequency, self.attack, self.decay, self.sustain, self.release, self.hold
+        )
+    }
+
+    /// Renders one mono note at `frequency` Hz, envelope included.
+    pub fn render(&self, frequency: f64, sample_rate: u32) -> Vec<f32> {
+        let rate = sample_rate as f64;
+        let frames = (self.duration() * rate).ceil() as usize;
+        let increment = frequency / rate; // Cycles per frame
+        let mut phase = 0.0f64;
+        let mut noise_seed: u32 = 0x1234_5678; // Fixed seed so renders are repeatable
+
+        let mut pcm = Vec::with_capacity(frames);
+        for frame in 0..frames {
+            let value = match self.waveform {
+                Waveform::Sine => (phase * std::f64::consts::TAU).sin(),
+                Waveform::Saw => 2.0 * phase - 1.0 - poly_blep(phase, increment),
+                Waveform::Square => {
+                    let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+                    naive + poly_blep(phase, increment) - poly_blep((phase + 0.5) % 1.0, increment)
+                },
+                Waveform::Noise => {
+                    // xorshift32
+                    noise_seed ^= noise_seed << 13;
+                    noise_seed ^= noise_seed >> 17;
+                    noise_seed ^= noise_seed << 5;
+                    noise_seed as f64 / u32::MAX as f64 * 2.0 - 1.0
+                },
+            };
+            pcm.push((value * self.envelope(frame as f64 / rate)) as f32 * SYNTH_LEVEL);
+
+            phase += increment;
+            if phase >= 1.0 {
+                phase -= 1.0;
+            }
+        }
+        pcm
+    }
+}
+
+// Smooths the jump of a saw or square wave over one sample either side, which
+// removes most of the aliasing of the naive waveform
+fn poly_blep(phase: f64, increment: f64) -> f64 {
+    if phase < increment {
+        let t = phase / increment;
+        2.0 * t - t * t - 1.0
+    } else if phase > 1.0 - increment {
+        let t = (phase - 1.0) / increment;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamped_limits_envelope_times_and_sustain() {
+        let mut settings = SynthSettings::new(Waveform::Sine);
+        settings.attack = 1e9;
+        settings.release = -1.0;
+        settings.sustain = 3.0;
+        let settings = settings.clamped();
+        assert_eq!(settings.attack, MAX_ENVELOPE_TIME);
+        assert_eq!(settings.release, 0.0);
+        assert_eq!(settings.sustain, 1.0);
+        assert!(settings.duration() <= 4.0 * MAX_ENVELOPE_TIME);
+    }
+
+    #[test]
+    fn render_covers_the_whole_envelope() {
+        let settings = SynthSettings::new(Waveform::Saw);
+        let pcm = settings.render(220.0, 1000);
+        assert_eq!(pcm.len(), (settings.duration() * 1000.0).ceil() as usize);
+        assert!(pcm.iter().all(|sample| sample.abs() <= 1.0));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,108 @@
+// Musical clock that runs on simulation time: 0 is the moment play starts
+
+pub const DEFAULT_BPM: f64 = 120.0;
+const BEATS_PER_BAR: u32 = 4;
+// Times this close past a grid line count as on it, so rounding noise doesn't delay a hit a whole step
+const GRID_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone)]
+pub struct Transport {
+    pub bpm: f64,
+    pub quantize: Option<f64>, // Grid as a fraction of a whole note (1/16), None = free timing
+    pub beats_per_bar: u32,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self {
+            bpm: DEFAULT_BPM,
+            quantize: None,
+            beats_per_bar: BEATS_PER_BAR,
+        }
+    }
+
+    // Seconds per beat (a quarter note)
+    pub fn beat_length(&self) -> f64 {
+        60.0 / self.bpm
+    }
+
+    // Seconds between quantize grid lines, if quantizing
+    pub fn grid_length(&self) -> Option<f64> {
+        self.quantize.map(|fraction| fraction * 4.0 * self.beat_length())
+    }
+
+    /// The first grid line at or after `time`, or `time` itself when not quantizing.
+    pub fn quantize_time(&self, time: f64) -> f64 {
+        match self.grid_length() {
+            Some(grid) if grid > 0.0 => ((time / grid) - GRID_EPSILON).ceil().max(0.0) * grid,
+            _ => time,
+        }
+    }
+
+    // Beat within the bar at `time`, counting from 1
+    pub fn beat_at(&self, time: f64) -> u64 {
+        self.total_beats(time) % self.beats_per_bar as u64 + 1
+    }
+
+    // Bar at `time`, counting from 1
+    pub fn bar_at(&self, time: f64) -> u64 {
+        self.total_beats(time) / self.beats_per_bar as u64 + 1
+    }
+
+    fn total_beats(&self, time: f64) -> u64 {
+        (time.max(0.0) / self.beat_length() + GRID_EPSILON).floor() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sixteenths_at(bpm: f64) -> Transport {
+        let mut transport = Transport::new();
+        transport.bpm = bpm;
+        transport.quantize = Some(1.0 / 16.0);
+        transport
+    }
+
+    #[test]
+    fn free_timing_leaves_time_alone() {
+        let transport = Transport::new();
+        assert_eq!(transport.quantize_time(0.3217), 0.3217);
+    }
+
+    #[test]
+    fn quantize_moves_to_the_next_grid_line() {
+        let transport = sixteenths_at(120.0); // Sixteenths are 0.125 s apart
+        assert_eq!(transport.quantize_time(0.0), 0.0);
+        assert_eq!(transport.quantize_time(0.01), 0.125);
+        assert_eq!(transport.quantize_time(0.124), 0.125);
+        assert_eq!(transport.quantize_time(0.126), 0.25);
+    }
+
+    #[test]
+    fn times_on_a_grid_line_stay_there() {
+        let transport = sixteenths_at(120.0);
+        for step in 0..64 {
+            let line = step as f64 * 0.125;
+            assert_eq!(transport.quantize_time(line), line);
+            // Accumulated float error just past the line must not push it a step later
+            assert_eq!(transport.quantize_time(line + 1e-12), line);
+        }
+    }
+
+    #[test]
+    fn negative_times_quantize_to_zero() {
+        let transport = sixteenths_at(120.0);
+        assert_eq!(transport.quantize_time(-0.5), 0.0);
+    }
+
+    #[test]
+    fn beats_and_bars_count_from_one() {
+        let transport = Transport::new(); // 120 bpm, half a second per beat
+        assert_eq!((transport.bar_at(0.0), transport.beat_at(0.0)), (1, 1));
+        assert_eq!((transport.bar_at(0.5), transport.beat_at(0.5)), (1, 2));
+        assert_eq!((transport.bar_at(2.0), transport.beat_at(2.0)), (2, 1));
+        assert_eq!((transport.bar_at(3.75), transport.beat_at(3.75)), (2, 4));
+    }
+}
\ No newline at end of file
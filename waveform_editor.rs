@@ -12,6 +12,17 @@ const WIDTH: u32 = 1200;
 const HEIGHT: u32 = 600;
 const WAVEFORM_HEIGHT: u32 = 400;
 const MARKER_HEIGHT: u32 = 200;
+// Onset detection works on blocks of this many samples
+const ONSET_HOP: usize = 512;
+// Onsets closer together than this (seconds) count as one hit
+const ONSET_MIN_GAP: f32 = 0.05;
+const DEFAULT_SENSITIVITY: f32 = 0.5;
+pub const DEFAULT_DIVISIONS: usize = 16;
+const DEFAULT_LOOP_BPM: f32 = 120.0;
+// Grid snap lands on sixteenth notes of the loop's tempo
+const GRID_STEPS_PER_BEAT: f32 = 4.0;
+// How far (seconds) either side of a marker to look for a zero crossing
+const ZERO_CROSSING_RANGE: f32 = 0.01;
 
 pub struct WaveformEditor {
     pixels: Option<Pixels>,
@@ -28,6 +39,11 @@ pub struct WaveformEditor {
     selected_marker: Option<usize>,
     loaded_sample_key: Option<String>,  // Track the loaded sample key for audio playback
     sample_rate: f32,  // Store sample rate for time calculations
+    onset_sensitivity: f32,  // 0.0 - 1.0, higher finds quieter hits
+    number_input: String,  // Digits typed before D (divisions) or B (loop BPM)
+    snap_to_zero: bool,  // Move markers to the nearest zero crossing
+    snap_to_grid: bool,  // Move markers to the nearest beat subdivision of loop_bpm
+    loop_bpm: f32,
 }
 
 impl WaveformEditor {
@@ -51,6 +67,11 @@ impl WaveformEditor {
             selected_marker: None,
             loaded_sample_key: None,
             sample_rate: 44100.0,  // Default sample rate
+            onset_sensitivity: DEFAULT_SENSITIVITY,
+            number_input: String::new(),
+            snap_to_zero: false,
+            snap_to_grid: false,
+            loop_bpm: DEFAULT_LOOP_BPM,
         };
 
         // Load audio file if provided
@@ -90,6 +111,11 @@ impl WaveformEditor {
             selected_marker: None,
             loaded_sample_key: None,
             sample_rate: 44100.0,  // Default sample rate
+            onset_sensitivity: DEFAULT_SENSITIVITY,
+            number_input: String::new(),
+            snap_to_zero: false,
+            snap_to_grid: false,
+            loop_bpm: DEFAULT_LOOP_BPM,
         };
 
         // Load audio file if provided
@@ -120,6 +146,11 @@ impl WaveformEditor {
             selected_marker: None,
             loaded_sample_key: None,
             sample_rate: 44100.0,  // Default sample rate
+            onset_sensitivity: DEFAULT_SENSITIVITY,
+            number_input: String::new(),
+            snap_to_zero: false,
+            snap_to_grid: false,
+            loop_bpm: DEFAULT_LOOP_BPM,
         }
     }
 
@@ -153,7 +184,9 @@ impl WaveformEditor {
         Ok(())
     }
 
-    // Static function to load audio samples without needing a WaveformEditor instance
+    // Static function to load audio samples without needing a WaveformEditor instance.
+    // Multichannel files are mixed down to one value per frame, so positions in the
+    // editor are frames and position / sample_rate is always seconds.
     pub fn load_samples_from_file(file_path: &str) -> Result<(Vec<f32>, f32), Box<dyn std::error::Error>> {
         use std::fs::File;
         use std::io::BufReader;
@@ -178,15 +211,16 @@ impl WaveformEditor {
         let buf_reader = BufReader::new(file);
         let decoder = Decoder::new(buf_reader)?;
         
-        // Get the sample rate before consuming the decoder
+        // Get the sample rate and channel count before consuming the decoder
         let sample_rate = decoder.sample_rate() as f32;
+        let channels = (decoder.channels() as usize).max(1);
         
         // Convert to f32 samples
         let samples: Vec<f32> = decoder
             .convert_samples::<f32>()
             .collect();
         
-        Ok((samples, sample_rate))
+        Ok((downmix(&samples, channels), sample_rate))
     }
 
     pub fn run(mut self, event_loop: EventLoop<()>) -> Result<std::collections::HashMap<String, Vec<usize>>, Box<dyn std::error::Error>> {
@@ -692,11 +726,16 @@ impl WaveformEditor {
     }
 
     // Slice marker methods
-    pub fn add_slice_marker(&mut self) {
-        self.slice_markers.push(self.cursor_position);
+    // Adds a marker at the cursor, snapped if a snap mode is on, and returns where it went
+    pub fn add_slice_marker(&mut self) -> f32 {
+        let position = self.snap_position(self.cursor_position);
+        if !self.slice_markers.contains(&position) {
+            self.slice_markers.push(position);
+        }
         // Sort markers by position and renumber them
         self.reorder_slice_markers();
-        println!("Added slice marker at position: {}", self.cursor_position);
+        println!("Added slice marker at position: {}", position);
+        position
     }
 
     // Reorder slice markers by position and maintain sequential numbering
@@ -716,6 +755,96 @@ impl WaveformEditor {
         }
     }
 
+    /// Replace the slice markers with the onsets found in the audio. Returns how many there are.
+    pub fn auto_slice(&mut self) -> usize {
+        self.slice_markers = detect_onsets(&self.audio_samples, self.sample_rate, self.onset_sensitivity);
+        self.snap_slice_markers();
+        self.slice_markers.len()
+    }
+
+    /// Replace the slice markers with `divisions` equal slices (slice into 16).
+    pub fn slice_into(&mut self, divisions: usize) -> usize {
+        self.slice_markers = equal_divisions(self.audio_samples.len(), divisions);
+        self.snap_slice_markers();
+        self.slice_markers.len()
+    }
+
+    pub fn change_onset_sensitivity(&mut self, delta: f32) -> f32 {
+        self.onset_sensitivity = ((self.onset_sensitivity + delta) * 10.0).round().clamp(0.0, 10.0) / 10.0;
+        self.onset_sensitivity
+    }
+
+    pub fn get_onset_sensitivity(&self) -> f32 {
+        self.onset_sensitivity
+    }
+
+    // Digits typed in waveform mode build up a number for the next D or B
+    pub fn push_digit(&mut self, digit: char) -> &str {
+        if self.number_input.len() < 3 {
+            self.number_input.push(digit);
+        }
+        &self.number_input
+    }
+
+    // The typed number (cleared afterwards), if there is one above 0
+    pub fn take_number(&mut self) -> Option<usize> {
+        let number = self.number_input.parse().ok().filter(|&n| n > 0);
+        self.number_input.clear();
+        number
+    }
+
+    /// Where a marker placed at `position` ends up: on the nearest beat subdivision
+    /// when grid snap is on, then on the nearest zero crossing when that is on.
+    pub fn snap_position(&self, position: f32) -> f32 {
+        let mut snapped = position;
+        if self.snap_to_grid && self.loop_bpm > 0.0 {
+            let step = self.sample_rate * 60.0 / self.loop_bpm / GRID_STEPS_PER_BEAT;
+            snapped = (snapped / step).round() * step;
+        }
+        snapped = snapped.round().clamp(0.0, self.audio_samples.len() as f32);
+        if self.snap_to_zero {
+            let range = (ZERO_CROSSING_RANGE * self.sample_rate) as usize;
+            snapped = nearest_zero_crossing(&self.audio_samples, snapped as usize, range) as f32;
+        }
+        snapped
+    }
+
+    // Snap every marker, dropping ones that land on the same spot or at the very end
+    fn snap_slice_markers(&mut self) {
+        let length = self.audio_samples.len() as f32;
+        let mut snapped: Vec<f32> = self.slice_markers.iter()
+            .map(|&marker| self.snap_position(marker))
+            .filter(|&marker| marker < length || length == 0.0)
+            .collect();
+        snapped.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        snapped.dedup();
+        self.slice_markers = snapped;
+    }
+
+    // Turning a snap mode on moves the markers that are already there too
+    pub fn toggle_zero_crossing_snap(&mut self) -> bool {
+        self.snap_to_zero = !self.snap_to_zero;
+        self.snap_slice_markers();
+        self.snap_to_zero
+    }
+
+    pub fn toggle_grid_snap(&mut self) -> bool {
+        self.snap_to_grid = !self.snap_to_grid;
+        self.snap_slice_markers();
+        self.snap_to_grid
+    }
+
+    // Tempo of the loaded loop; also turns grid snap on
+    pub fn set_loop_bpm(&mut self, bpm: f32) {
+        self.loop_bpm = bpm;
+        self.snap_to_grid = true;
+        self.snap_slice_markers();
+    }
+
+    pub fn get_loop_bpm(&self) -> f32 {
+        self.loop_bpm
+    }
+
     pub fn clear_slice_markers(&mut self) {
         self.slice_markers.clear();
     }
@@ -727,6 +856,20 @@ impl WaveformEditor {
         println!("Loaded {} slice markers", marker_count);
     }
 
+    // Markers are kept as frame positions here but saved in seconds, like the engine's
+    pub fn slice_markers_in_seconds(&self) -> Vec<f64> {
+        self.slice_markers.iter()
+            .map(|&position| position as f64 / self.sample_rate as f64)
+            .collect()
+    }
+
+    pub fn load_slice_markers_from_seconds(&mut self, seconds: &[f64]) {
+        let positions = seconds.iter()
+            .map(|&time| (time * self.sample_rate as f64) as f32)
+            .collect();
+        self.load_slice_markers(positions);
+    }
+
     // Method to update cursor position from external source (for integrated mode)
     pub fn set_cursor_position(&mut self, position: f32) {
         self.cursor_position = position;
@@ -743,4 +886,235 @@ impl WaveformEditor {
     pub fn get_loaded_sample_key(&self) -> Option<&str> {
         self.loaded_sample_key.as_deref()
     }
+}
+
+// Averages interleaved samples into one value per frame
+fn downmix(samples: &[f32], channels: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return samples.to_vec();
+    }
+    samples.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+/// Finds where hits start in mono `samples`, as frame positions. Each block's energy is
+/// compared on a log scale with the block before it; rises that are local peaks become
+/// onsets. Higher `sensitivity` (0.0 - 1.0) accepts smaller rises and quieter hits.
+pub fn detect_onsets(samples: &[f32], sample_rate: f32, sensitivity: f32) -> Vec<f32> {
+    let energies: Vec<f32> = samples.chunks(ONSET_HOP)
+        .map(|block| block.iter().map(|s| s * s).sum::<f32>() / block.len() as f32)
+        .collect();
+    let loudest = energies.iter().cloned().fold(0.0f32, f32::max);
+    if loudest <= 0.0 {
+        return Vec::new();
+    }
+
+    let sensitivity = sensitivity.clamp(0.0, 1.0);
+    // Natural log of the energy ratio: 0.5 is about +2 dB, 4.0 about +17 dB
+    let threshold = 4.0 - 3.5 * sensitivity;
+    // Blocks more than 12 dB (sensitivity 0) to 48 dB (sensitivity 1) below the loudest never start a slice
+    let gate = 10f32.powf(-(12.0 + 36.0 * sensitivity) / 10.0);
+    let floor = loudest * gate * gate;
+    let level = |index: usize| energies[index].max(floor).ln();
+    let flux: Vec<f32> = (0..energies.len())
+        .map(|index| {
+            let previous = if index == 0 { floor.ln() } else { level(index - 1) };
+            if energies[index] < loudest * gate { 0.0 } else { (level(index) - previous).max(0.0) }
+        })
+        .collect();
+
+    let min_gap = (ONSET_MIN_GAP * sample_rate) as usize;
+    let mut onsets: Vec<f32> = Vec::new();
+    for index in 0..flux.len() {
+        let is_peak = index == 0 || flux[index] >= flux[index - 1];
+        let is_peak = is_peak && flux.get(index + 1).map_or(true, |&next| flux[index] >= next);
+        if flux[index] < threshold || !is_peak {
+            continue;
+        }
+
+        // Start of the hit within the block: the first sample above a tenth of its peak
+        let start = index * ONSET_HOP;
+        let block = &samples[start..(start + ONSET_HOP).min(samples.len())];
+        let peak = block.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let offset = block.iter().position(|s| s.abs() >= peak * 0.1).unwrap_or(0);
+        let position = (start + offset) as f32;
+
+        if onsets.last().map_or(true, |&last| position - last >= min_gap as f32) {
+            onsets.push(position);
+        }
+    }
+    onsets
+}
+
+/// Start frames of `divisions` equal slices of `length` frames. With more divisions
+/// than frames every frame starts one slice; with no frames there are none.
+pub fn equal_divisions(length: usize, divisions: usize) -> Vec<f32> {
+    let divisions = divisions.max(1);
+    let mut markers: Vec<f32> = (0..divisions)
+        .map(|index| (index as f32 * length as f32 / divisions as f32).floor())
+        .filter(|&marker| marker < length as f32)
+        .collect();
+    markers.dedup();
+    markers
+}
+
+// The sample nearest `position` (within `range`) where the waveform changes sign,
+// so a slice starting there doesn't click. `position` itself if there is none.
+fn nearest_zero_crossing(samples: &[f32], position: usize, range: usize) -> usize {
+    if position == 0 || position >= samples.len() {
+        return position;
+    }
+    let is_crossing = |index: usize| {
+        index > 0 && index < samples.len()
+            && (samples[index] == 0.0 || (samples[index - 1] < 0.0) != (samples[index] < 0.0))
+    };
+    for distance in 0..=range {
+        if is_crossing(position + distance) {
+            return position + distance;
+        }
+        if distance <= position && is_crossing(position - distance) {
+            return position - distance;
+        }
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16-bit PCM WAV with the given interleaved samples
+    fn wav_bytes(samples: &[i16], channels: u16, sample_rate: u32) -> Vec<u8> {
+        let data_length = (samples.len() * 2) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_length).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&channels.to_le_bytes());
+        bytes.extend_from_slice(&sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(sample_rate * channels as u32 * 2).to_le_bytes());
+        bytes.extend_from_slice(&(channels * 2).to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_length.to_le_bytes());
+        for sample in samples {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn editor_with(frames: Vec<f32>, sample_rate: f32) -> WaveformEditor {
+        let mut editor = WaveformEditor::new_integrated();
+        editor.sample_rate = sample_rate;
+        editor.load_audio(frames);
+        editor
+    }
+
+    #[test]
+    fn stereo_files_load_as_one_value_per_frame() {
+        // Left is always positive and right always negative, so the raw interleaved
+        // data crosses zero on every sample while the mix never does
+        let frames = 1000;
+        let samples: Vec<i16> = (0..frames).flat_map(|_| [16000i16, -8000]).collect();
+        let path = std::env::temp_dir().join(format!("cant_waveform_stereo_{}.wav", std::process::id()));
+        std::fs::write(&path, wav_bytes(&samples, 2, 8000)).unwrap();
+        let loaded = WaveformEditor::load_samples_from_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+
+        let (samples, sample_rate) = loaded.expect("loads");
+        assert_eq!(sample_rate, 8000.0);
+        assert_eq!(samples.len(), frames);
+        assert!(samples.iter().all(|&s| (s - 4000.0 / 32768.0).abs() < 0.001));
+    }
+
+    #[test]
+    fn downmix_averages_each_frame() {
+        assert_eq!(downmix(&[1.0, -1.0, 0.5, 0.0], 2), vec![0.0, 0.25]);
+        assert_eq!(downmix(&[0.1, 0.2], 1), vec![0.1, 0.2]);
+    }
+
+    #[test]
+    fn grid_snap_steps_are_sixteenths_in_frames() {
+        // 60 BPM at 1000 Hz: a beat is 1000 frames and a sixteenth 250
+        let mut editor = editor_with(vec![0.5; 4000], 1000.0);
+        editor.set_loop_bpm(60.0);
+        assert_eq!(editor.snap_position(240.0), 250.0);
+        assert_eq!(editor.snap_position(620.0), 500.0);
+        assert_eq!(editor.snap_position(3990.0), 4000.0);
+    }
+
+    #[test]
+    fn zero_crossing_snap_finds_the_sign_change() {
+        let mut frames = vec![0.5; 100];
+        frames[40..].iter_mut().for_each(|s| *s = -0.5);
+        let mut editor = editor_with(frames, 1000.0); // 10 frames either side
+        editor.toggle_zero_crossing_snap();
+        assert_eq!(editor.snap_position(35.0), 40.0);
+        assert_eq!(editor.snap_position(45.0), 40.0);
+        assert_eq!(editor.snap_position(80.0), 80.0); // Nothing in range
+    }
+
+    #[test]
+    fn slice_into_makes_equal_parts() {
+        let mut editor = editor_with(vec![0.5; 1600], 1000.0);
+        assert_eq!(editor.slice_into(16), 16);
+        let expected: Vec<f32> = (0..16).map(|index| (index * 100) as f32).collect();
+        assert_eq!(editor.get_slice_markers(), &expected);
+
+        // Uneven lengths round each start down
+        let mut editor = editor_with(vec![0.5; 10], 1000.0);
+        assert_eq!(editor.slice_into(3), 3);
+        assert_eq!(editor.get_slice_markers(), &vec![0.0, 3.0, 6.0]);
+    }
+
+    #[test]
+    fn slice_into_handles_empty_and_short_audio() {
+        let mut editor = editor_with(Vec::new(), 1000.0);
+        assert_eq!(editor.slice_into(16), 0);
+        assert!(editor.get_slice_markers().is_empty());
+
+        // More divisions than frames: one slice per frame, no repeats
+        let mut editor = editor_with(vec![0.5; 4], 1000.0);
+        assert_eq!(editor.slice_into(16), 4);
+        assert_eq!(editor.get_slice_markers(), &vec![0.0, 1.0, 2.0, 3.0]);
+
+        // Zero divisions count as one
+        assert_eq!(editor.slice_into(0), 1);
+        assert_eq!(equal_divisions(0, 8), Vec::<f32>::new());
+    }
+
+    // One second at 44.1 kHz with two 5 ms bursts, the first at 0.5 s and the second `gap_ms` later
+    fn two_bursts(gap_ms: usize) -> Vec<f32> {
+        let mut frames = vec![0.0f32; 44100];
+        for start in [22050, 22050 + gap_ms * 441 / 10] {
+            frames[start..start + 220].iter_mut().for_each(|s| *s = 0.8);
+        }
+        frames
+    }
+
+    #[test]
+    fn onsets_closer_than_the_minimum_gap_merge() {
+        // 30 ms apart is inside the 50 ms gap, so the second burst is dropped
+        let onsets = detect_onsets(&two_bursts(30), 44100.0, 0.5);
+        assert_eq!(onsets, vec![22050.0]);
+
+        // 60 ms apart both count
+        let onsets = detect_onsets(&two_bursts(60), 44100.0, 0.5);
+        assert_eq!(onsets, vec![22050.0, 24696.0]);
+    }
+
+    #[test]
+    fn saved_markers_round_trip_through_seconds() {
+        let mut editor = editor_with(vec![0.0; 48000], 48000.0);
+        editor.load_slice_markers(vec![12000.0, 24000.0, 36000.0]);
+        let seconds = editor.slice_markers_in_seconds();
+        assert_eq!(seconds, vec![0.25, 0.5, 0.75]);
+
+        let mut reopened = editor_with(vec![0.0; 48000], 48000.0);
+        reopened.load_slice_markers_from_seconds(&seconds);
+        assert_eq!(reopened.get_slice_markers(), &vec![12000.0, 24000.0, 36000.0]);
+    }
 }
\ No newline at end of file